@@ -1,33 +1,61 @@
 use ::reqwest::IntoUrl;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 use governor::{Quota, RateLimiter};
-use http::header::CONTENT_DISPOSITION;
+use http::header::{
+    HeaderMap, HeaderName, ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE, RANGE,
+    RETRY_AFTER,
+};
 use http::Method;
-use indicatif::ProgressStyle;
+use indicatif::{MultiProgress, ProgressBar};
 use nonzero_ext::*;
 use pollster::FutureExt as _;
+use regex::Regex;
 use reqwest::blocking as reqwest;
-use serde::Serialize;
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
 use soup::prelude::*;
-use std::error::Error;
+use std::collections::HashMap;
 use std::fs::{self, File};
-use std::io::BufReader;
-use std::path::Path;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use std::str;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 pub mod structs;
 use crate::api::structs::{
-    DigitalItem, DownloadInfo, DownloadsMap, Item, ParsedCollectionItems, ParsedFanpageData,
-    ParsedItemsData, RawDownloadsMap,
+    digital_item::{DigitalItemDownload, Track, TralbumDetails},
+    BandMusicItem, DigitalItem, DownloadInfo, DownloadsMap, FollowedBand, Item, ItemSource,
+    MerchItem, MerchMap, ParsedCollectionItems, ParsedFanpageData, ParsedFollowingBands,
+    ParsedItemFormats, ParsedItemsData, ParsedWishlistItems, RawDownloadsMap, TralbumData,
+    WishlistItem,
 };
 use crate::cookies;
+use crate::error::BandsnatchError;
 use crate::util;
 
+#[derive(Default)]
 pub struct BandcampPage {
     pub download_urls: DownloadsMap,
+    /// Merch purchases without anything to download.
+    pub merch: MerchMap,
+    /// Gifts that haven't been redeemed yet, so can only be downloaded through
+    /// their gift link.
+    pub gifts: DownloadsMap,
     // pub page_name: String,
 }
 
+impl BandcampPage {
+    /// Drop downloads put out by a label not matching `label`. Ones without a
+    /// known label are kept, so they can be checked once their details are
+    /// fetched.
+    fn retain_label(&mut self, label: &Regex) {
+        let matches = |info: &DownloadInfo| info.label.as_deref().is_none_or(|l| label.is_match(l));
+        self.download_urls.retain(|_, info| matches(info));
+        self.gifts.retain(|_, info| matches(info));
+    }
+}
+
 /// Body used to paginate through Bandcamp's collection API.
 #[derive(Serialize, Debug)]
 struct PostCollectionBody<'a> {
@@ -37,67 +65,491 @@ struct PostCollectionBody<'a> {
 
 const MAX_RETRIES: u8 = 5;
 
+const DIGEST: HeaderName = HeaderName::from_static("digest");
+const REPR_DIGEST: HeaderName = HeaderName::from_static("repr-digest");
+
+/// Options that affect how releases get downloaded.
+#[derive(Clone, Default)]
+pub struct DownloadOptions {
+    /// Continue `.part` files from where they left off.
+    pub resume: bool,
+    /// Limit on download speed, shared between all jobs.
+    pub throttle: Option<Arc<Mutex<util::TokenBucket>>>,
+    /// Check downloaded files against their checksum, or at least that
+    /// archives can be opened.
+    pub verify: bool,
+    /// Download each file over this many connections at once, if the server
+    /// supports ranges. Anything under 2 uses a single connection.
+    pub parallel_chunks: u64,
+    /// Keep album zips after extracting them.
+    pub keep_zip: bool,
+    /// What to extract album zips with.
+    pub extractor: util::ExtractorKind,
+}
+
+/// A release that's been downloaded, but not extracted yet.
+pub struct Download {
+    /// How many bytes were actually downloaded.
+    pub bytes: u64,
+    /// Where the file was saved.
+    pub file: PathBuf,
+}
+
+/// Extract a downloaded album zip into the folder it was saved to, removing it
+/// afterwards unless `options.keep_zip` is set.
+pub fn extract_album(
+    archive: &Path,
+    m: &MultiProgress,
+    options: &DownloadOptions,
+) -> Result<(), BandsnatchError> {
+    m.suspend(|| debug!("Unzipping `{}`", archive.display()));
+    let dest = archive.parent().unwrap_or(Path::new("."));
+    options.extractor.extractor().extract(archive, dest)?;
+    if options.keep_zip {
+        m.suspend(|| debug!("Unzipped archive"));
+    } else {
+        fs::remove_file(archive)?;
+        m.suspend(|| debug!("Unzipped and removed original archive"));
+    }
+
+    Ok(())
+}
+
+/// Whether `res` is a web page, rather than the file we asked for.
+fn is_html(res: &reqwest::Response) -> bool {
+    res.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/html"))
+}
+
+/// Content types that Bandcamp sends album archives with.
+const ARCHIVE_CONTENT_TYPES: [&str; 2] = ["application/zip", "application/octet-stream"];
+
+/// Make sure that an album download is being sent as a zip, if it says what
+/// it's being sent as.
+fn check_archive_content_type(res: &reqwest::Response) -> Result<(), BandsnatchError> {
+    let Some(content_type) = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return Ok(());
+    };
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    if ARCHIVE_CONTENT_TYPES
+        .iter()
+        .any(|t| mime.eq_ignore_ascii_case(t))
+    {
+        return Ok(());
+    }
+
+    Err(BandsnatchError::UnexpectedContent(format!(
+        "expected a zip file but got `{content_type}` from {}",
+        res.url()
+    )))
+}
+
+/// Make sure that the file at `path` starts like a zip file, rather than
+/// being an error page.
+fn check_zip_magic(path: &Path) -> Result<(), BandsnatchError> {
+    let mut magic = [0u8; 4];
+    // Anything too short to have the magic can't be a zip either.
+    let _ = File::open(path)?.read_exact(&mut magic);
+    if magic == *b"PK\x03\x04" {
+        return Ok(());
+    }
+
+    Err(BandsnatchError::UnexpectedContent(format!(
+        "{} is not a zip file, Bandcamp may have sent an error page instead",
+        path.display()
+    )))
+}
+
+/// Copy `stream` to `file`, throttled if there's a limit on download speed.
+fn copy_stream<R: Read>(
+    stream: R,
+    file: &mut impl Write,
+    pb: Option<&ProgressBar>,
+    options: &DownloadOptions,
+) -> std::io::Result<u64> {
+    match &options.throttle {
+        Some(bucket) => {
+            let mut stream = util::ThrottledReader::new(stream, bucket.clone());
+            util::copy_with_progress(&mut stream, file, pb)
+        }
+        None => {
+            let mut stream = stream;
+            util::copy_with_progress(&mut stream, file, pb)
+        }
+    }
+}
+
+/// Pull the digital items out of the data blob on a download page.
+fn parse_digital_items<T: DeserializeOwned>(html: &str, url: &str) -> Result<T, BandsnatchError> {
+    let soup = Soup::new(html);
+    let blob = soup
+        .attr("id", "pagedata")
+        .find()
+        .ok_or_else(|| {
+            BandsnatchError::Parse(format!(
+                "could not find `pagedata` element for digital item {url}"
+            ))
+        })?
+        .get("data-blob")
+        .ok_or_else(|| {
+            BandsnatchError::Parse(format!(
+                "could not extract `data-blob` from the pagedata element for digital item {url}"
+            ))
+        })?;
+
+    serde_json::from_str(&blob)
+        .map_err(|e| BandsnatchError::Parse(format!("failed parsing {url}: {e}")))
+}
+
+/// Read the releases listed on an artist or label's music page. Only the first
+/// few are in the grid itself, the rest are left in `data-client-items` for the
+/// page's scripts to fill in.
+fn parse_music_grid(html: &str) -> Result<Vec<BandMusicItem>, BandsnatchError> {
+    let soup = Soup::new(html);
+    let Some(grid) = soup.attr("id", "music-grid").find() else {
+        return Ok(vec![]);
+    };
+
+    let mut items = grid
+        .tag("li")
+        .find_all()
+        .filter_map(|li| {
+            let item_id = li.get("data-item-id")?;
+            let (item_type, id) = item_id.split_once('-')?;
+            let page_url = li.tag("a").find()?.get("href")?;
+            let title_el = li.class("title").find()?;
+            let mut title = title_el.text();
+            let artist = title_el.class("artist-override").find().map(|a| {
+                let artist = a.text();
+                if let Some(i) = title.rfind(&artist) {
+                    title.truncate(i);
+                }
+                artist.trim().to_owned()
+            });
+            Some(BandMusicItem {
+                id: id.to_owned(),
+                item_type: item_type.to_owned(),
+                title: title.trim().to_owned(),
+                artist,
+                page_url,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(blob) = grid.get("data-client-items") {
+        items.extend(serde_json::from_str::<Vec<BandMusicItem>>(&blob)?);
+    }
+    Ok(items)
+}
+
+/// Save the raw HTML of the page at `url` to a temporary file that can be
+/// attached to bug reports, and print where it went.
+fn print_debug_page(url: &str, html: &str) {
+    // Named after the release where possible, so pages don't overwrite each
+    // other.
+    let id = url::Url::parse(url)
+        .ok()
+        .and_then(|u| {
+            u.query_pairs()
+                .find(|(k, _)| k == "sitem_id" || k == "gift_id")
+                .map(|(_, v)| v.into_owned())
+        })
+        .unwrap_or_else(|| format!("{:x}", Sha256::digest(url.as_bytes()))[..8].to_string());
+    let path = std::env::temp_dir().join(format!("bandsnatch-debug-{id}.html"));
+
+    match fs::write(&path, html) {
+        Ok(()) => info!("Saved the full page to {}", path.display()),
+        Err(e) => warn!("Failed to save the full page to {}: {e}", path.display()),
+    }
+}
+
+/// Lossless formats, from most to least preferred.
+const LOSSLESS_FORMATS: &[&str] = &["flac", "alac", "aiff-lossless", "wav"];
+
+/// Pick the most preferred lossless format out of a release's downloads.
+pub fn best_lossless_format(
+    downloads: &HashMap<String, DigitalItemDownload>,
+) -> Option<&'static str> {
+    LOSSLESS_FORMATS
+        .iter()
+        .find(|f| downloads.contains_key(**f))
+        .copied()
+}
+
+/// How long a `Retry-After` header asks us to wait, either as a number of
+/// seconds or until a HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+        .or(Some(Duration::ZERO))
+}
+
+/// Get the SHA-256 checksum of a response from its `Repr-Digest` or `Digest`
+/// header, if it has one.
+fn expected_sha256(headers: &HeaderMap) -> Option<Vec<u8>> {
+    [REPR_DIGEST, DIGEST]
+        .iter()
+        .filter_map(|name| headers.get(name)?.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|digest| {
+            let (algorithm, value) = digest.trim().split_once('=')?;
+            if !algorithm.eq_ignore_ascii_case("sha-256") {
+                return None;
+            }
+            // `Repr-Digest` wraps the value in colons, `Digest` doesn't.
+            BASE64.decode(value.trim_matches(':')).ok()
+        })
+}
+
+/// Check a finished download against `expected` if given, otherwise make sure
+/// that archives have a readable central directory.
+fn verify_download(
+    path: &Path,
+    expected: Option<&[u8]>,
+    is_archive: bool,
+) -> Result<(), BandsnatchError> {
+    if let Some(expected) = expected {
+        let actual = util::sha256_file(path)?;
+        if actual != expected {
+            return Err(BandsnatchError::Api(format!(
+                "checksum mismatch for {}",
+                path.display()
+            )));
+        }
+        return Ok(());
+    }
+
+    if is_archive {
+        let reader = BufReader::new(File::open(path)?);
+        zip::ZipArchive::new(reader).map_err(|e| {
+            BandsnatchError::Api(format!("{} is not a valid zip file: {e}", path.display()))
+        })?;
+    }
+
+    Ok(())
+}
+
+/// How many times to get a new download link for a release whose link has
+/// expired, before giving up.
+const MAX_URL_REFRESHES: u32 = 2;
+
+/// Options for the HTTP client used to talk to Bandcamp.
+#[derive(Clone, Default)]
+pub struct ClientOptions {
+    /// Proxy to send all requests through. The usual `http_proxy` and
+    /// `https_proxy` environment variables are used if not set.
+    pub proxy: Option<::reqwest::Proxy>,
+    /// User agent to send instead of `DEFAULT_USER_AGENT`.
+    pub user_agent: Option<String>,
+    /// How long to wait to connect to Bandcamp.
+    pub connect_timeout: Option<Duration>,
+    /// How long to wait for each read (or write) before giving up.
+    pub read_timeout: Option<Duration>,
+    /// How many idle connections to keep around for each host. Unlimited if
+    /// not set.
+    pub max_connections: Option<usize>,
+    /// Where Bandcamp's pages and APIs are, instead of `DEFAULT_BASE_URL`.
+    pub base_url: Option<String>,
+    /// How many API calls (as opposed to downloads) can be made at once,
+    /// across all threads. Unlimited if not set.
+    pub max_api_calls: Option<usize>,
+}
+
+/// How often to send TCP keepalives on idle connections, so that they aren't
+/// dropped during long downloads.
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+/// Where Bandcamp's pages and APIs are usually found.
+pub const DEFAULT_BASE_URL: &str = "https://bandcamp.com";
+
+/// Looks like a normal browser, so that Bandcamp treats us like one.
+pub const DEFAULT_USER_AGENT: &str =
+    "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0";
+
+/// Parse a proxy URL, making sure that it's a scheme we can use.
+pub fn parse_proxy(s: &str) -> Result<::reqwest::Proxy, String> {
+    let url = url::Url::parse(s).map_err(|e| format!("Invalid proxy URL '{s}': {e}."))?;
+    if !["http", "https", "socks5", "socks5h"].contains(&url.scheme()) {
+        return Err(format!(
+            "Unsupported proxy scheme `{}`. Supported schemes are http, https, socks5, and socks5h.",
+            url.scheme()
+        ));
+    }
+
+    ::reqwest::Proxy::all(url).map_err(|e| format!("Invalid proxy URL '{s}': {e}."))
+}
+
+/// Parse the URL that Bandcamp's pages and APIs are under, e.g. for a
+/// self-hosted copy.
+pub fn parse_base_url(s: &str) -> Result<String, String> {
+    let url = url::Url::parse(s).map_err(|e| format!("Invalid URL '{s}': {e}."))?;
+    if !["http", "https"].contains(&url.scheme()) {
+        return Err(format!(
+            "Unsupported scheme `{}`. Only http and https are supported.",
+            url.scheme()
+        ));
+    }
+
+    Ok(url.as_str().trim_end_matches('/').to_string())
+}
+
+/// Client for Bandcamp's APIs. Create it once and share it, as it holds the
+/// pool of connections reused between requests.
 pub struct Api {
     pub client: reqwest::Client,
     ratelimiter: governor::DefaultDirectRateLimiter,
+    progress: Option<Arc<MultiProgress>>,
+    base_url: String,
+    api_calls: Option<util::Semaphore>,
 }
 
 impl Api {
     pub fn new(cookies: Vec<cookies::RawCookie>) -> Self {
+        Self::with_options(cookies, ClientOptions::default())
+    }
+
+    pub fn with_options(cookies: Vec<cookies::RawCookie>, options: ClientOptions) -> Self {
         let cookie_jar = cookies::fill_cookie_jar(cookies);
-        let client = reqwest::ClientBuilder::new()
+        let user_agent = options.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT);
+        let mut builder = reqwest::ClientBuilder::new()
             .cookie_provider(Arc::new(cookie_jar))
-            .build()
-            .unwrap();
+            .user_agent(user_agent)
+            .connect_timeout(options.connect_timeout)
+            .timeout(options.read_timeout)
+            .tcp_keepalive(TCP_KEEPALIVE);
+        if let Some(max_connections) = options.max_connections {
+            builder = builder.pool_max_idle_per_host(max_connections);
+        }
+        if let Some(proxy) = options.proxy {
+            builder = builder.proxy(proxy);
+        }
+        let client = builder.build().unwrap();
         let ratelimiter = RateLimiter::direct(Quota::per_second(nonzero!(3u32)));
+        let base_url = options
+            .base_url
+            .as_deref()
+            .unwrap_or(DEFAULT_BASE_URL)
+            .trim_end_matches('/')
+            .to_string();
 
         Self {
             client,
             ratelimiter,
+            progress: None,
+            base_url,
+            api_calls: options.max_api_calls.map(util::Semaphore::new),
         }
     }
 
-    fn bc_path(path: &str) -> String {
-        format!("https://bandcamp.com/{path}")
+    fn bc_path(&self, path: &str) -> String {
+        format!("{}/{path}", self.base_url)
+    }
+
+    /// Wait for a turn to call one of Bandcamp's APIs, if they're limited.
+    /// Hold on to the permit until the whole response has been read.
+    fn api_permit(&self) -> Option<util::SemaphorePermit<'_>> {
+        self.api_calls.as_ref().map(util::Semaphore::acquire)
+    }
+
+    /// Get the body of the page at `url`, as an API call.
+    fn get_text(&self, url: &str) -> Result<String, BandsnatchError> {
+        let _permit = self.api_permit();
+        Ok(self.request(Method::GET, url)?.text()?)
+    }
+
+    /// Show messages (like when being ratelimited) through `m`, instead of
+    /// only logging them.
+    pub fn with_progress(mut self, m: Arc<MultiProgress>) -> Self {
+        self.progress = Some(m);
+        self
     }
 
     fn request<U: IntoUrl + Copy>(
         &self,
         method: Method,
         url: U,
-    ) -> Result<reqwest::Response, Box<dyn Error>> {
-        self.request_with_retry(method, url, 0)
+    ) -> Result<reqwest::Response, BandsnatchError> {
+        self.execute_with_backoff(|| self.client.request(method.clone(), url))
     }
 
-    fn request_with_retry<U: IntoUrl + Copy>(
+    /// Send the request created by `build`, retrying it if Bandcamp ratelimits
+    /// us or it times out. Waits for as long as Bandcamp asks through `Retry-After`, or
+    /// otherwise backs off exponentially from a second.
+    fn execute_with_backoff<F: Fn() -> reqwest::RequestBuilder>(
         &self,
-        method: Method,
-        url: U,
-        retry_attempt: u8,
-    ) -> Result<reqwest::Response, Box<dyn Error>> {
-        self.ratelimiter.until_ready().block_on();
+        build: F,
+    ) -> Result<reqwest::Response, BandsnatchError> {
+        let mut backoff = Duration::from_secs(1);
+        let mut wait = backoff;
 
-        let response = self.client.request(method.clone(), url.clone()).send()?;
-        let status: http::StatusCode = response.status();
+        for attempt in 0..=MAX_RETRIES {
+            self.ratelimiter.until_ready().block_on();
 
-        if !status.is_success() {
+            let request = build().build()?;
+            let url = request.url().clone();
+            let response = match self.client.execute(request) {
+                Ok(response) => response,
+                Err(e) if e.is_timeout() && attempt < MAX_RETRIES => {
+                    let msg = format!(
+                        "Request to {url} timed out, retrying in {}s",
+                        backoff.as_secs()
+                    );
+                    match &self.progress {
+                        Some(m) => m.suspend(|| warn!("{msg}")),
+                        None => warn!("{msg}"),
+                    }
+                    std::thread::sleep(backoff);
+                    backoff *= 2;
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let status: http::StatusCode = response.status();
+
+            if status.is_success() {
+                return Ok(response);
+            }
             if status != http::StatusCode::TOO_MANY_REQUESTS {
-                bail!(
-                    "request failed with status {status} for url {}",
-                    url.as_str()
-                );
+                return Err(BandsnatchError::Status { status, url });
             }
-
-            if retry_attempt >= MAX_RETRIES {
-                bail!(format!("reached maximum retries for url {}", url.as_str()));
+            wait = retry_after(response.headers()).unwrap_or(backoff);
+            if attempt == MAX_RETRIES {
+                break;
             }
 
-            warn!("hit ratelimit from Bandcamp, sleeping for 10 seconds");
-            std::thread::sleep(std::time::Duration::from_secs(10));
-            return self.request_with_retry(method, url, retry_attempt + 1);
+            backoff *= 2;
+            self.wait_for_ratelimit(wait);
         }
 
-        Ok(response)
+        Err(BandsnatchError::RateLimit(wait))
+    }
+
+    fn wait_for_ratelimit(&self, wait: Duration) {
+        let message = format!("Rate limited by Bandcamp, waiting {}s", wait.as_secs());
+        let Some(m) = &self.progress else {
+            warn!("{message}");
+            std::thread::sleep(wait);
+            return;
+        };
+
+        let pb = m.add(ProgressBar::new_spinner().with_message(message));
+        pb.enable_steady_tick(Duration::from_millis(100));
+        std::thread::sleep(wait);
+        pb.finish_and_clear();
+        m.remove(&pb);
     }
 
     /// Filters the download map by optional artist or album filters.
@@ -105,8 +557,8 @@ impl Api {
     fn filter_download_map<'a>(
         unfiltered: Option<RawDownloadsMap>,
         items: &'a Vec<&'a Item>,
-        album: Option<&String>,
-        artist: Option<&String>,
+        album: Option<&Regex>,
+        artist: Option<&Regex>,
     ) -> DownloadsMap {
         unfiltered
             .iter()
@@ -115,14 +567,22 @@ impl Api {
                 items
                     .iter()
                     .find(|v| &format!("{}{}", v.sale_item_type, v.sale_item_id) == id)
-                    .filter(|item| artist.is_none_or(|v| item.band_name.eq_ignore_ascii_case(v)))
-                    .filter(|item| album.is_none_or(|v| item.item_title.eq_ignore_ascii_case(v)))
+                    .filter(|item| artist.is_none_or(|v| v.is_match(&item.band_name)))
+                    .filter(|item| album.is_none_or(|v| v.is_match(&item.item_title)))
                     .map(|item| {
                         (
                             id.clone(),
                             DownloadInfo {
                                 url: url.clone(),
                                 purchased: item.purchased.clone(),
+                                artist: item.band_name.clone(),
+                                title: item.item_title.clone(),
+                                source: ItemSource::Collection,
+                                preorder: item.is_preorder,
+                                release_date: item.release_date.clone(),
+                                gift: false,
+                                label: item.label.clone(),
+                                release_id: item.release_id(),
                             },
                         )
                     })
@@ -130,21 +590,103 @@ impl Api {
             .collect::<DownloadsMap>()
     }
 
-    fn download_fanpage_data(&self, name: &str) -> Result<ParsedFanpageData, Box<dyn Error>> {
+    /// Finds merch purchases that have nothing to download, using the same
+    /// filters as `filter_download_map`.
+    fn filter_merch(
+        urls: &RawDownloadsMap,
+        items: &[&Item],
+        album: Option<&Regex>,
+        artist: Option<&Regex>,
+    ) -> MerchMap {
+        items
+            .iter()
+            .filter(|item| item.is_merch())
+            .map(|item| {
+                (
+                    format!("{}{}", item.sale_item_type, item.sale_item_id),
+                    item,
+                )
+            })
+            .filter(|(id, _)| !urls.contains_key(id))
+            .filter(|(_, item)| artist.is_none_or(|v| v.is_match(&item.band_name)))
+            .filter(|(_, item)| album.is_none_or(|v| v.is_match(&item.item_title)))
+            .map(|(id, item)| {
+                (
+                    id,
+                    MerchItem {
+                        artist: item.band_name.clone(),
+                        title: item.item_title.clone(),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Finds gifts that don't have a download link yet, and points them at
+    /// their gift link instead, using the same filters as `filter_download_map`.
+    fn filter_gifts(
+        &self,
+        urls: &RawDownloadsMap,
+        items: &[&Item],
+        album: Option<&Regex>,
+        artist: Option<&Regex>,
+    ) -> DownloadsMap {
+        items
+            .iter()
+            .filter(|item| !item.is_merch())
+            .filter_map(|item| {
+                let gift_id = item.gift_id?;
+                Some((
+                    format!("{}{}", item.sale_item_type, item.sale_item_id),
+                    item,
+                    gift_id,
+                ))
+            })
+            .filter(|(id, _, _)| !urls.contains_key(id))
+            .filter(|(_, item, _)| artist.is_none_or(|v| v.is_match(&item.band_name)))
+            .filter(|(_, item, _)| album.is_none_or(|v| v.is_match(&item.item_title)))
+            .map(|(id, item, gift_id)| {
+                (
+                    id,
+                    DownloadInfo {
+                        url: self.bc_path(&format!(
+                            "download?gift_id={gift_id}&sitem_id={}",
+                            item.sale_item_id
+                        )),
+                        purchased: item.purchased.clone(),
+                        artist: item.band_name.clone(),
+                        title: item.item_title.clone(),
+                        source: ItemSource::Collection,
+                        preorder: item.is_preorder,
+                        release_date: item.release_date.clone(),
+                        gift: true,
+                        label: item.label.clone(),
+                        release_id: item.release_id(),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn download_fanpage_data(&self, name: &str) -> Result<ParsedFanpageData, BandsnatchError> {
         debug!("`download_fanpage_data` for Bandcamp page '{name}'");
 
-        let body = self.request(Method::GET, &Self::bc_path(name))?.text()?;
+        let body = self.get_text(&self.bc_path(name))?;
         let soup = Soup::new(&body);
 
-        let data_el = soup
-            .attr("id", "pagedata")
-            .find()
-            .expect("Failed to extract data from collection page.");
-        let data_blob = data_el
-            .get("data-blob")
-            .expect("Failed to extract data from element on collection page.");
-        let fanpage_data: ParsedFanpageData = serde_json::from_str(&data_blob)
-            .expect("Failed to deserialise collection page data blob.");
+        let data_el = soup.attr("id", "pagedata").find().ok_or_else(|| {
+            BandsnatchError::Parse(format!(
+                "could not find `pagedata` element on page for {name}"
+            ))
+        })?;
+        let data_blob = data_el.get("data-blob").ok_or_else(|| {
+            BandsnatchError::Parse(format!(
+                "could not find `data-blob` attribute on page for {name}"
+            ))
+        })?;
+        let fanpage_data: ParsedFanpageData = serde_json::from_str(&data_blob).map_err(|e| {
+            BandsnatchError::Parse(format!("failed to read data blob on page for {name}: {e}"))
+        })?;
         debug!("Successfully fetched Bandcamp page, and found + deserialised data blob");
 
         Ok(fanpage_data)
@@ -154,12 +696,13 @@ impl Api {
     pub fn get_download_urls(
         &self,
         name: &str,
-        artist: Option<&String>,
-        album: Option<&String>,
-    ) -> Result<BandcampPage, Box<dyn Error>> {
+        artist: Option<&Regex>,
+        album: Option<&Regex>,
+        label: Option<&Regex>,
+    ) -> Result<BandcampPage, BandsnatchError> {
         debug!("`get_download_urls` for Bandcamp page '{name}'");
 
-        let fanpage_data = self.download_fanpage_data(&name)?;
+        let fanpage_data = self.download_fanpage_data(name)?;
         let items = fanpage_data
             .item_cache
             .collection
@@ -168,10 +711,13 @@ impl Api {
 
         match fanpage_data.fan_data.is_own_page {
             Some(true) => (),
-            _ => bail!(format!(
-                r#"Failed to scrape collection data for "{name}" (`is_own_page` is false). Perhaps check your cookies, or your spelling."#
-            )),
+            _ => {
+                return Err(BandsnatchError::Auth(format!(
+                    r#"Failed to scrape collection data for "{name}" (`is_own_page` is false). Perhaps check your cookies, or your spelling."#
+                )));
+            }
         }
+        debug!("Found fan ID {} for {name}", fanpage_data.fan_data.fan_id);
 
         let mut collection = Self::filter_download_map(
             fanpage_data.collection_data.redownload_urls.clone(),
@@ -179,6 +725,14 @@ impl Api {
             album,
             artist,
         );
+        let empty_urls = RawDownloadsMap::new();
+        let redownload_urls = fanpage_data
+            .collection_data
+            .redownload_urls
+            .as_ref()
+            .unwrap_or(&empty_urls);
+        let mut merch = Self::filter_merch(redownload_urls, &items, album, artist);
+        let mut gifts = self.filter_gifts(redownload_urls, &items, album, artist);
 
         let skip_hidden_items = true;
         if skip_hidden_items {
@@ -194,12 +748,15 @@ impl Api {
                 fanpage_data.collection_data.item_count.unwrap()
             );
             let rest = self.get_rest_downloads_in_collection(
-                &fanpage_data,
+                &fanpage_data.fan_data.fan_id,
                 "collection_items",
+                fanpage_data.collection_data.last_token.clone().unwrap(),
                 album,
                 artist,
             )?;
-            collection.extend(rest);
+            collection.extend(rest.download_urls);
+            merch.extend(rest.merch);
+            gifts.extend(rest.gifts);
         }
 
         if !skip_hidden_items
@@ -210,59 +767,86 @@ impl Api {
                 fanpage_data.hidden_data.item_count.unwrap()
             );
             let rest = self.get_rest_downloads_in_collection(
-                &fanpage_data,
+                &fanpage_data.fan_data.fan_id,
                 "hidden_items",
+                fanpage_data.hidden_data.last_token.clone().unwrap(),
                 album,
                 artist,
             )?;
-            collection.extend(rest);
+            collection.extend(rest.download_urls);
+            merch.extend(rest.merch);
+            gifts.extend(rest.gifts);
         }
 
-        debug!("Successfully retrieved all download URLs");
-        Ok(BandcampPage {
+        let mut page = BandcampPage {
             // page_name: title,
             download_urls: collection,
-        })
+            merch,
+            gifts,
+        };
+        if let Some(label) = label {
+            page.retain_label(label);
+        }
+
+        debug!("Successfully retrieved all download URLs");
+        Ok(page)
     }
 
-    /// Loop over a user's collection to retrieve all paginated items.
+    /// Loop over a fan's collection to retrieve all paginated items older than
+    /// `last_token`.
     fn get_rest_downloads_in_collection(
         &self,
-        data: &ParsedFanpageData,
+        fan_id: &str,
         collection_name: &str,
-        album: Option<&String>,
-        artist: Option<&String>,
-    ) -> Result<DownloadsMap, Box<dyn Error>> {
+        mut last_token: String,
+        album: Option<&Regex>,
+        artist: Option<&Regex>,
+    ) -> Result<BandcampPage, BandsnatchError> {
         debug!("Paginating results for {collection_name}");
-        let collection_data = match collection_name {
-            "collection_items" => &data.collection_data,
-            "hidden_items" => &data.hidden_data,
-            x => bail!(format!(r#"unexpected value for `collection_name`: "{x}""#)),
-        };
-
-        let mut last_token = collection_data.last_token.clone().unwrap();
         let mut more_available = true;
         let mut collection = DownloadsMap::new();
+        let mut merch = MerchMap::new();
+        let mut gifts = DownloadsMap::new();
+        let spinner = self.progress.as_ref().map(|m| {
+            let pb = m.add(ProgressBar::new_spinner());
+            pb.enable_steady_tick(Duration::from_millis(100));
+            pb
+        });
+        let mut page = 0;
 
         while more_available {
             trace!("More items to collect, looping...");
-            // retries
+            page += 1;
+            let message = format!("Fetching more of the collection (page {page})...");
+            match &spinner {
+                Some(pb) => pb.set_message(message),
+                None => debug!("{message}"),
+            }
+
             let request_body = PostCollectionBody {
-                fan_id: &data.fan_data.fan_id,
+                fan_id,
                 older_than_token: &last_token,
             };
+            let url = self.bc_path(&format!("api/fancollection/1/{collection_name}"));
+            let _permit = self.api_permit();
             let response_body = self
-                .client
-                .post(&Self::bc_path(&format!(
-                    "api/fancollection/1/{collection_name}"
-                )))
-                .json(&request_body)
-                .send()?
+                .execute_with_backoff(|| self.client.post(&url).json(&request_body))?
                 .json::<ParsedCollectionItems>()?;
 
             let items = response_body.items.iter().by_ref().collect::<Vec<_>>();
-            let redownload_urls =
-                Self::filter_download_map(Some(response_body.redownload_urls), &items, album, artist);
+            merch.extend(Self::filter_merch(
+                &response_body.redownload_urls,
+                &items,
+                album,
+                artist,
+            ));
+            gifts.extend(self.filter_gifts(&response_body.redownload_urls, &items, album, artist));
+            let redownload_urls = Self::filter_download_map(
+                Some(response_body.redownload_urls),
+                &items,
+                album,
+                artist,
+            );
             trace!("Collected {} items", redownload_urls.len());
 
             collection.extend(redownload_urls);
@@ -270,84 +854,723 @@ impl Api {
             last_token = response_body.last_token;
         }
 
-        debug!("Finished paginating results for {collection_name}");
-        Ok(collection)
+        if let (Some(m), Some(pb)) = (&self.progress, spinner) {
+            pb.finish_and_clear();
+            m.remove(&pb);
+        }
+        debug!("Finished paginating results for {collection_name} ({page} pages)");
+        Ok(BandcampPage {
+            download_urls: collection,
+            merch,
+            gifts,
+        })
+    }
+
+    /// Find download urls for the collection of the fan with `fan_id` straight
+    /// from the collection API, without needing their username.
+    pub fn get_fan_download_urls(
+        &self,
+        fan_id: u64,
+        artist: Option<&Regex>,
+        album: Option<&Regex>,
+        label: Option<&Regex>,
+    ) -> Result<BandcampPage, BandsnatchError> {
+        debug!("`get_fan_download_urls` for fan ID {fan_id}");
+
+        // Tokens start with a timestamp, so this asks for everything bought
+        // before now.
+        let token = format!("{}::a::", chrono::Utc::now().timestamp());
+        let mut page = self.get_rest_downloads_in_collection(
+            &fan_id.to_string(),
+            "collection_items",
+            token,
+            album,
+            artist,
+        )?;
+        if let Some(label) = label {
+            page.retain_label(label);
+        }
+
+        debug!("Successfully retrieved all download URLs");
+        Ok(page)
+    }
+
+    /// Filters wishlist items by optional artist or album filters, mapping them
+    /// to their release page.
+    fn filter_wishlist<'a, I: IntoIterator<Item = &'a WishlistItem>>(
+        items: I,
+        album: Option<&Regex>,
+        artist: Option<&Regex>,
+    ) -> DownloadsMap {
+        items
+            .into_iter()
+            .filter(|item| artist.is_none_or(|v| v.is_match(&item.band_name)))
+            .filter(|item| album.is_none_or(|v| v.is_match(&item.item_title)))
+            .map(|item| {
+                (
+                    format!("{}{}", item.item_type, item.item_id),
+                    DownloadInfo {
+                        url: item.item_url.clone(),
+                        purchased: None,
+                        artist: item.band_name.clone(),
+                        title: item.item_title.clone(),
+                        source: ItemSource::Wishlist,
+                        preorder: false,
+                        release_date: None,
+                        gift: false,
+                        label: None,
+                        release_id: Some(format!("{}{}", item.item_type, item.item_id)),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Scrape a user's Bandcamp wishlist page to find the items in it.
+    pub fn get_wishlist_urls(
+        &self,
+        name: &str,
+        artist: Option<&Regex>,
+        album: Option<&Regex>,
+    ) -> Result<BandcampPage, BandsnatchError> {
+        debug!("`get_wishlist_urls` for Bandcamp page '{name}'");
+
+        let fanpage_data = self.download_fanpage_data(&format!("{name}/wishlist"))?;
+        let mut wishlist =
+            Self::filter_wishlist(fanpage_data.item_cache.wishlist.values(), album, artist);
+
+        let Some(wishlist_data) = &fanpage_data.wishlist_data else {
+            return Err(BandsnatchError::Parse(format!(
+                r#"Failed to find wishlist data for "{name}". Perhaps check your spelling."#
+            )));
+        };
+
+        if wishlist_data.item_count > wishlist_data.batch_size {
+            debug!(
+                "Too many in `wishlist_data`, so we need to paginate ({} total)",
+                wishlist_data.item_count.unwrap()
+            );
+
+            let mut last_token = wishlist_data.last_token.clone().unwrap();
+            let mut more_available = true;
+
+            while more_available {
+                trace!("More wishlist items to collect, looping...");
+                let request_body = PostCollectionBody {
+                    fan_id: &fanpage_data.fan_data.fan_id,
+                    older_than_token: &last_token,
+                };
+                let _permit = self.api_permit();
+                let url = self.bc_path("api/fancollection/1/wishlist_items");
+                let response_body = self
+                    .execute_with_backoff(|| self.client.post(&url).json(&request_body))?
+                    .json::<ParsedWishlistItems>()?;
+
+                wishlist.extend(Self::filter_wishlist(&response_body.items, album, artist));
+                more_available = response_body.more_available;
+                last_token = response_body.last_token;
+            }
+        }
+
+        debug!("Successfully retrieved all wishlist items");
+        Ok(BandcampPage {
+            download_urls: wishlist,
+            merch: MerchMap::new(),
+            gifts: DownloadsMap::new(),
+        })
+    }
+
+    /// Get all the artists and labels a user follows.
+    fn get_following_bands(&self, name: &str) -> Result<Vec<FollowedBand>, BandsnatchError> {
+        debug!("`get_following_bands` for Bandcamp page '{name}'");
+
+        let fanpage_data = self.download_fanpage_data(&format!("{name}/following"))?;
+        let mut bands = fanpage_data
+            .item_cache
+            .following_bands
+            .into_values()
+            .collect::<Vec<_>>();
+
+        let Some(following_data) = &fanpage_data.following_bands_data else {
+            return Err(BandsnatchError::Parse(format!(
+                r#"Failed to find following data for "{name}". Perhaps check your spelling."#
+            )));
+        };
+
+        if following_data.item_count > following_data.batch_size {
+            debug!(
+                "Too many in `following_bands_data`, so we need to paginate ({} total)",
+                following_data.item_count.unwrap()
+            );
+
+            let mut last_token = following_data.last_token.clone().unwrap();
+            let mut more_available = true;
+
+            while more_available {
+                trace!("More followed bands to collect, looping...");
+                let request_body = PostCollectionBody {
+                    fan_id: &fanpage_data.fan_data.fan_id,
+                    older_than_token: &last_token,
+                };
+                let _permit = self.api_permit();
+                let url = self.bc_path("api/fancollection/1/following_bands");
+                let response_body = self
+                    .execute_with_backoff(|| self.client.post(&url).json(&request_body))?
+                    .json::<ParsedFollowingBands>()?;
+
+                bands.extend(response_body.followers);
+                more_available = response_body.more_available;
+                last_token = response_body.last_token;
+            }
+        }
+
+        Ok(bands)
+    }
+
+    /// The root of a followed artist or label's pages, on the same host as
+    /// everything else unless they have their own domain.
+    fn band_url(&self, band: &FollowedBand) -> Option<String> {
+        if let Some(domain) = &band.url_hints.custom_domain {
+            return Some(format!("https://{domain}"));
+        }
+        let subdomain = band.url_hints.subdomain.as_deref()?;
+        let mut url = url::Url::parse(&self.base_url).ok()?;
+        let host = format!("{subdomain}.{}", url.host_str()?);
+        url.set_host(Some(&host)).ok()?;
+        Some(url.as_str().trim_end_matches('/').to_owned())
+    }
+
+    /// Find the releases put out by the artists and labels a user follows.
+    /// These haven't necessarily been bought, so like wishlist items they point
+    /// at the release's page, and only free ones can be downloaded.
+    pub fn get_following_urls(
+        &self,
+        name: &str,
+        artist: Option<&Regex>,
+        album: Option<&Regex>,
+    ) -> Result<BandcampPage, BandsnatchError> {
+        let bands = self.get_following_bands(name)?;
+        debug!("Found {} followed artists and labels", bands.len());
+
+        let mut download_urls = DownloadsMap::new();
+        for band in bands {
+            let Some(band_url) = self.band_url(&band) else {
+                warn!("Couldn't find the page of {}, skipping it", band.name);
+                continue;
+            };
+            let body = self.get_text(&format!("{band_url}/music"))?;
+            let items = parse_music_grid(&body)?;
+            debug!("Found {} releases on {band_url}", items.len());
+
+            for item in items {
+                let item_artist = item.artist.unwrap_or_else(|| band.name.clone());
+                if !artist.is_none_or(|v| v.is_match(&item_artist))
+                    || !album.is_none_or(|v| v.is_match(&item.title))
+                {
+                    continue;
+                }
+
+                let url = if item.page_url.starts_with("http") {
+                    item.page_url
+                } else {
+                    format!("{band_url}{}", item.page_url)
+                };
+                let id = format!("{}{}", item.item_type, item.id);
+                download_urls.insert(
+                    id.clone(),
+                    DownloadInfo {
+                        url,
+                        purchased: None,
+                        artist: item_artist,
+                        title: item.title,
+                        source: ItemSource::Following,
+                        preorder: false,
+                        release_date: None,
+                        gift: false,
+                        label: None,
+                        release_id: Some(id),
+                    },
+                );
+            }
+        }
+
+        debug!("Successfully retrieved all releases from followed artists and labels");
+        Ok(BandcampPage {
+            download_urls,
+            merch: MerchMap::new(),
+            gifts: DownloadsMap::new(),
+        })
+    }
+
+    /// Find the page that the release at `release_url` can be downloaded from
+    /// for free, without being logged in. `None` if it has to be bought.
+    pub fn get_free_download_page(
+        &self,
+        release_url: &str,
+    ) -> Result<Option<String>, BandsnatchError> {
+        debug!("Checking whether {release_url} is free to download");
+        let body = self.get_text(release_url)?;
+        let soup = Soup::new(&body);
+        let Some(blob) = soup
+            .tag("script")
+            .find_all()
+            .find_map(|script| script.get("data-tralbum"))
+        else {
+            return Err(BandsnatchError::Parse(format!(
+                "couldn't find release data on {release_url}"
+            )));
+        };
+
+        let data = serde_json::from_str::<TralbumData>(&blob)?;
+        Ok(data.free_download_page)
+    }
+
+    /// List the formats that the release on the download page at `item_url`
+    /// can be downloaded in, sorted. Bandcamp only puts these in the page
+    /// itself so it still has to be fetched, but this skips parsing the rest of
+    /// the release and fetching long tracklists. Use
+    /// `DigitalItem::available_formats` instead if the item's already fetched.
+    pub fn list_available_formats(
+        &self,
+        item_url: &str,
+        debug: &bool,
+    ) -> Result<Vec<String>, BandsnatchError> {
+        debug!("Listing formats for {item_url}");
+        let text = self.get_text(item_url)?;
+        let parsed = parse_digital_items::<ParsedItemFormats>(&text, item_url);
+        let downloads = match parsed {
+            Ok(parsed) => parsed.digital_items.into_iter().next().map(|i| i.downloads),
+            Err(e) => {
+                if *debug {
+                    print_debug_page(item_url, &text);
+                }
+                return Err(e);
+            }
+        };
+        let Some(downloads) = downloads else {
+            if *debug {
+                print_debug_page(item_url, &text);
+            }
+            return Err(BandsnatchError::Parse(format!(
+                "no digital items found for {item_url}"
+            )));
+        };
+
+        let mut formats = downloads
+            .into_iter()
+            .flat_map(|d| d.into_keys())
+            .collect::<Vec<_>>();
+        formats.sort_unstable();
+        Ok(formats)
     }
 
     pub fn get_digital_item(
         &self,
         url: &str,
         debug: &bool,
-    ) -> Result<Option<DigitalItem>, Box<dyn Error>> {
+    ) -> Result<Option<DigitalItem>, BandsnatchError> {
         debug!("Retrieving digital item information for {url}");
-        let text = self.request(Method::GET, url)?.text()?;
-        let soup = Soup::new(&text);
+        let text = self.get_text(url)?;
+        let parsed = match parse_digital_items::<ParsedItemsData>(&text, url) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                if *debug {
+                    warn!("Failed to get item info for {url}");
+                    print_debug_page(url, &text);
+                } else {
+                    warn!("Failed to get item info for {url}, run with `--debug` to save the full page");
+                }
 
-        let download_page_blob = soup
-            .attr("id", "pagedata")
-            .find()
-            .expect(&format!(
-                "could not find `pagedata` element for digital item {url}"
-            ))
-            .get("data-blob")
-            .expect(&format!(
-                "could not extract `data-blob` from the pagedata element for digital item {url}"
-            ));
-
-        let item_result = std::panic::catch_unwind(|| {
-            serde_json::from_str::<ParsedItemsData>(&download_page_blob).unwrap()
-        });
+                return Err(e);
+            }
+        };
 
-        if item_result.is_err() {
-            println!("Failed to get item info for {url}.");
+        let Some(mut item) = parsed.digital_items.first().cloned() else {
             if *debug {
-                println!("\n{download_page_blob}\n");
+                warn!("No digital items found for {url}");
+                print_debug_page(url, &text);
+            }
+            return Ok(None);
+        };
+        item.download_page = Some(url.to_string());
+
+        if item.has_more_tracks {
+            match self.get_all_tracks(&item) {
+                Ok(tracks) if tracks.len() > item.tracks.len() => item.tracks = tracks,
+                Ok(_) => {}
+                Err(e) => warn!(
+                    "Only found {} tracks of {}, failed to get the rest: {e}",
+                    item.tracks.len(),
+                    item.title
+                ),
+            }
+        }
+
+        Ok(Some(item))
+    }
+
+    /// Get a new download link for `item` in `audio_format`, for when the one
+    /// it has has expired.
+    fn refresh_download_url(
+        &self,
+        item: &DigitalItem,
+        audio_format: &str,
+    ) -> Result<String, BandsnatchError> {
+        let Some(page) = &item.download_page else {
+            return Err(BandsnatchError::Api(format!(
+                "don't know where to get a new download link for {}",
+                item.title
+            )));
+        };
+
+        self.get_digital_item(page, &false)?
+            .and_then(|fresh| fresh.downloads?.remove(audio_format))
+            .map(|download| download.url)
+            .ok_or_else(|| {
+                BandsnatchError::Api(format!(
+                    "{} can no longer be downloaded as {audio_format}",
+                    item.title
+                ))
+            })
+    }
+
+    /// Ask how big `item` is in `audio_format`, without downloading it.
+    pub fn download_size(
+        &self,
+        item: &DigitalItem,
+        audio_format: &str,
+    ) -> Result<Option<u64>, BandsnatchError> {
+        let Some(download) = item.downloads.as_ref().and_then(|d| d.get(audio_format)) else {
+            return Ok(None);
+        };
+
+        let res = self.request(Method::HEAD, &download.url)?;
+        if is_html(&res) {
+            return Ok(None);
+        }
+        // `Response::content_length` is always 0 for HEAD requests, as there's
+        // no body.
+        Ok(res
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok()))
+    }
+
+    /// Check whether the cookies being used are logged in, returning the
+    /// username they're logged in as.
+    pub fn check_login(&self) -> Result<Option<String>, BandsnatchError> {
+        let summary = self
+            .request(Method::GET, &self.bc_path("api/fan/2/collection_summary"))?
+            .json::<serde_json::Value>()?;
+        if summary.get("fan_id").is_none() {
+            return Ok(None);
+        }
+
+        let username = summary
+            .pointer("/collection_summary/username")
+            .and_then(|u| u.as_str())
+            .unwrap_or("unknown user");
+        Ok(Some(username.to_string()))
+    }
+
+    /// Fetch the full tracklist of `item`, for releases where the download page
+    /// only has the first few tracks.
+    fn get_all_tracks(&self, item: &DigitalItem) -> Result<Vec<Track>, BandsnatchError> {
+        let (Some(item_id), Some(band_id)) = (item.item_id, item.band_id) else {
+            return Err(BandsnatchError::Parse(String::from(
+                "missing the IDs needed to look it up",
+            )));
+        };
+        let tralbum_type = if item.is_single() { "t" } else { "a" };
+
+        debug!("Fetching full tracklist for {}", item.title);
+        let _permit = self.api_permit();
+        let details = self
+            .request(
+                Method::GET,
+                &self.bc_path(&format!(
+                    "api/mobile/24/tralbum_details?band_id={band_id}&tralbum_type={tralbum_type}&tralbum_id={item_id}"
+                )),
+            )?
+            .json::<TralbumDetails>()?;
+
+        Ok(details.tracks)
+    }
+
+    /// Save the cover art of `item` as `filename` inside of `path`. Uses the
+    /// `cover.jpg` that comes in album downloads if there is one, and otherwise
+    /// fetches it from Bandcamp.
+    pub fn download_cover(
+        &self,
+        item: &DigitalItem,
+        path: &str,
+        filename: &str,
+    ) -> Result<(), BandsnatchError> {
+        let dest = Path::new(path).join(filename);
+        let bundled = Path::new(path).join("cover.jpg");
+        if bundled.exists() {
+            if bundled != dest {
+                fs::copy(&bundled, &dest)?;
+            }
+            return Ok(());
+        }
+
+        let Some(url) = item.cover_url() else {
+            return Err(BandsnatchError::Api(format!(
+                "{} - {} doesn't have any cover art",
+                item.title, item.artist
+            )));
+        };
+        debug!("Downloading cover art from {url} to `{}`", dest.display());
+
+        let mut res = self
+            .request(Method::GET, url.as_str())?
+            .error_for_status()?;
+        let mut file = File::create(&dest)?;
+        res.copy_to(&mut file)?;
+
+        Ok(())
+    }
+
+    /// Write the body of a download to `part_path`, continuing from what is
+    /// already there if `options.resume` is set and the server says how long
+    /// the download is.
+    fn write_download(
+        &self,
+        res: reqwest::Response,
+        download_url: &str,
+        part_path: &Path,
+        pb: Option<&ProgressBar>,
+        m: &MultiProgress,
+        options: &DownloadOptions,
+    ) -> Result<u64, BandsnatchError> {
+        let Some(len) = res.content_length() else {
+            // Nothing to check a `.part` file or chunks against, so all there
+            // is to do is take the body as it comes.
+            m.suspend(|| debug!("No length given for download, so not resuming"));
+            let mut file = File::create(part_path)?;
+            let written = copy_stream(res, &mut file, pb, options)?;
+            file.sync_all()?;
+            return Ok(written);
+        };
+        let existing_len = if options.resume {
+            fs::metadata(part_path).map(|m| m.len()).unwrap_or(0)
+        } else {
+            0
+        };
+
+        let accepts_ranges = res
+            .headers()
+            .get(ACCEPT_RANGES)
+            .is_some_and(|v| v.as_bytes() == b"bytes");
+        if options.parallel_chunks > 1 && accepts_ranges && existing_len == 0 && len > 0 {
+            drop(res);
+            m.suspend(|| debug!("Downloading in {} chunks", options.parallel_chunks));
+            // Chunks leave holes in the file until they're all done, so they go
+            // somewhere else so that `part_path` is only ever resumed from
+            // when it's a prefix of the download.
+            let chunks_path = util::chunks_path(part_path);
+            let result = self
+                .chunked_download(download_url, &chunks_path, len, pb, options)
+                .and_then(|written| {
+                    fs::rename(&chunks_path, part_path)?;
+                    Ok(written)
+                });
+            if result.is_err() {
+                let _ = fs::remove_file(&chunks_path);
+            }
+            return result;
+        }
+
+        // Whatever's already there can't be anything but a prefix of the
+        // download, so one that's too long is from something else and has to
+        // be started again.
+        let (stream, mut file) = if existing_len > 0 && existing_len < len {
+            // Don't need the full body anymore, so let the connection go
+            // before asking for the rest of it.
+            drop(res);
+            m.suspend(|| debug!("Trying to resume download from byte {existing_len}"));
+            let res = self.execute_with_backoff(|| {
+                self.client
+                    .get(download_url)
+                    .header(RANGE, format!("bytes={existing_len}-"))
+            })?;
+
+            if res.status() == http::StatusCode::PARTIAL_CONTENT {
+                if let Some(pb) = pb {
+                    pb.set_position(existing_len);
+                }
+                let file = File::options().append(true).open(part_path)?;
+                (res, file)
             } else {
-                println!("Run with `--debug` to see the full JSON blob.\n")
+                m.suspend(|| debug!("Server doesn't support resuming, starting again"));
+                (res, File::create(part_path)?)
             }
+        } else {
+            (res, File::create(part_path)?)
+        };
+        m.suspend(|| debug!("Starting download"));
 
-            bail!(format!("failed parsing {url}"))
+        let written = copy_stream(stream, &mut file, pb, options)?;
+        if let Some(pb) = pb {
+            pb.set_position(len);
         }
+        file.sync_all()?;
+
+        Ok(written)
+    }
+
+    /// Download the `len` bytes at `download_url` into `part_path`, split into
+    /// equal byte ranges which are all fetched at once.
+    fn chunked_download(
+        &self,
+        download_url: &str,
+        part_path: &Path,
+        len: u64,
+        pb: Option<&ProgressBar>,
+        options: &DownloadOptions,
+    ) -> Result<u64, BandsnatchError> {
+        // Each chunk writes into its own part of the file.
+        File::create(part_path)?.set_len(len)?;
+        let chunk_len = len.div_ceil(options.parallel_chunks);
+
+        let results = std::thread::scope(|scope| {
+            let handles = (0..len)
+                .step_by(chunk_len as usize)
+                .map(|start| {
+                    let end = (start + chunk_len).min(len) - 1;
+                    scope.spawn(move || {
+                        self.download_range(download_url, part_path, start, end, pb, options)
+                    })
+                })
+                .collect::<Vec<_>>();
 
-        let item = item_result.unwrap().digital_items.first().cloned();
+            handles
+                .into_iter()
+                .map(|h| {
+                    h.join().unwrap_or_else(|_| {
+                        Err(BandsnatchError::Api(String::from("chunk panicked")))
+                    })
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let mut written = 0;
+        for result in results {
+            written += result?;
+        }
+        File::options().write(true).open(part_path)?.sync_all()?;
+
+        Ok(written)
+    }
+
+    /// Download bytes `start..=end` of `download_url` into the same place in
+    /// `part_path`.
+    fn download_range(
+        &self,
+        download_url: &str,
+        part_path: &Path,
+        start: u64,
+        end: u64,
+        pb: Option<&ProgressBar>,
+        options: &DownloadOptions,
+    ) -> Result<u64, BandsnatchError> {
+        let res = self.execute_with_backoff(|| {
+            self.client
+                .get(download_url)
+                .header(RANGE, format!("bytes={start}-{end}"))
+        })?;
+        if res.status() != http::StatusCode::PARTIAL_CONTENT {
+            return Err(BandsnatchError::Api(format!(
+                "server ignored request for bytes {start}-{end}"
+            )));
+        }
+
+        let mut file = File::options().write(true).open(part_path)?;
+        file.seek(SeekFrom::Start(start))?;
+        let written = copy_stream(res, &mut file, pb, options)?;
+        if written != end - start + 1 {
+            return Err(BandsnatchError::Api(format!(
+                "expected {} bytes from {start}-{end}, got {written}",
+                end - start + 1
+            )));
+        }
 
-        Ok(item)
+        Ok(written)
     }
 
+    /// Download a release to `path`, reporting progress to `pb` if there is
+    /// one. Finishing or abandoning `pb` is left up to the caller, as is
+    /// extracting albums with `extract_album`.
     pub fn download_item(
         &self,
         item: &DigitalItem,
         path: &str,
         audio_format: &str,
-        m: &indicatif::MultiProgress,
-    ) -> Result<(), Box<dyn Error>> {
-        let download_url = &item
+        pb: Option<&ProgressBar>,
+        m: &MultiProgress,
+        options: &DownloadOptions,
+    ) -> Result<Download, BandsnatchError> {
+        let mut download_url = item
             .downloads
             .as_ref()
             .expect("cannot download a release with no downloads")
             .get(audio_format)
             .unwrap()
-            .url;
-        let res = self.request(Method::GET, download_url)?;
+            .url
+            .clone();
+
+        // Links expire after a while, either giving a 403 or redirecting to
+        // an error page.
+        let mut refreshes = 0;
+        let res = loop {
+            match self.request(Method::GET, &download_url) {
+                Ok(res) if !is_html(&res) => break res,
+                Ok(_) => {}
+                Err(BandsnatchError::Status {
+                    status: http::StatusCode::FORBIDDEN,
+                    ..
+                }) => {}
+                Err(e) => return Err(e),
+            }
+
+            if refreshes == MAX_URL_REFRESHES {
+                return Err(BandsnatchError::Api(format!(
+                    "download link for {} keeps expiring",
+                    item.title
+                )));
+            }
+            refreshes += 1;
+            m.suspend(|| {
+                debug!(
+                    "Download link for {} expired, getting a new one",
+                    item.title
+                )
+            });
+            download_url = self.refresh_download_url(item, audio_format)?;
+        };
+        let download_url = download_url.as_str();
+        if !item.is_single() {
+            check_archive_content_type(&res)?;
+        }
 
-        let len = res.content_length().unwrap();
         let full_title = format!("{} - {}", item.title, item.artist);
-        let pb = m.add(
-            indicatif::ProgressBar::new(len)
-                .with_message(full_title.clone())
-                .with_style(
-                    ProgressStyle::with_template("{bar:10} ({bytes}/{total_bytes}) {wide_msg}")
-                        .unwrap(),
-                ),
-        );
+        if let Some(pb) = pb {
+            pb.reset();
+            pb.set_style(util::download_style());
+            if let Some(len) = res.content_length() {
+                pb.set_length(len);
+            }
+            pb.set_message(full_title.clone());
+        }
 
         let disposition = res.headers().get(CONTENT_DISPOSITION);
 
-        if let None = disposition {
-            pb.finish_and_clear();
-            return Err(
-                format!("could not download {full_title} when using url `{download_url}`").into(),
-            );
+        if disposition.is_none() {
+            return Err(BandsnatchError::Api(format!(
+                "could not download {full_title} when using url `{download_url}`"
+            )));
         }
 
         // `HeaderValue::to_str` only handles valid ASCII bytes, and Bandcamp
@@ -365,34 +1588,216 @@ impl Api {
         .trim_matches('"');
         m.suspend(|| debug!("Downloading as `{filename}` to `{path}`"));
 
-        // TODO: drop file with `.part` extension instead, while downloading, and then rename when finished?.
-
         let full_path = Path::new(path).join(filename);
-        let mut file = File::create(&full_path)?;
-        let mut stream = res;
-        m.suspend(|| debug!("Starting download"));
+        let part_path = util::part_path(&full_path);
+        let expected_checksum = expected_sha256(res.headers());
 
-        util::copy_with_progress(&mut stream, &mut file, &pb)?;
-        pb.set_position(len);
-
-        // Close downloaded file.
-        drop(file);
+        let written = match self.write_download(res, download_url, &part_path, pb, m, options) {
+            Ok(written) => written,
+            Err(e) => {
+                // Keep what we've got if we might be able to resume it later.
+                if !options.resume {
+                    let _ = fs::remove_file(&part_path);
+                }
+                return Err(e);
+            }
+        };
 
         if !item.is_single() {
-            m.suspend(|| debug!("Unzipping album"));
-            let file = File::open(&full_path)?;
-            let reader = BufReader::new(file);
-            let mut archive = zip::ZipArchive::new(reader)?;
+            if let Err(e) = check_zip_magic(&part_path) {
+                let _ = fs::remove_file(&part_path);
+                return Err(e);
+            }
+        }
 
-            archive.extract(path)?;
-            fs::remove_file(&full_path)?;
-            m.suspend(|| debug!("Unzipped and removed original archive"));
+        if options.verify {
+            m.suspend(|| debug!("Verifying `{}`", part_path.display()));
+            if let Err(e) =
+                verify_download(&part_path, expected_checksum.as_deref(), !item.is_single())
+            {
+                // Bad data isn't worth resuming from.
+                let _ = fs::remove_file(&part_path);
+                return Err(e);
+            }
         }
-        // Cover folder downloading for singles
 
-        pb.finish_and_clear();
-        m.println(format!("(Done) {full_title}"))?;
+        fs::rename(&part_path, &full_path)?;
 
-        Ok(())
+        Ok(Download {
+            bytes: written,
+            file: full_path,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, Write};
+    use std::net::TcpListener;
+
+    /// Answer a single request on a local port with `headers` and `body`,
+    /// returning the URL to request.
+    fn serve_once(headers: &str, body: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let response = format!("HTTP/1.1 200 OK\r\n{headers}Connection: close\r\n\r\n");
+
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            stream.write_all(body).unwrap();
+        });
+
+        format!("http://{addr}/download")
+    }
+
+    fn track(url: &str) -> DigitalItem {
+        serde_json::from_value(serde_json::json!({
+            "downloads": { "flac": { "url": url } },
+            "package_release_date": null,
+            "title": "Title",
+            "artist": "Artist",
+            "download_type": null,
+            "download_type_str": "track",
+            "item_type": "track",
+            "art_id": null,
+        }))
+        .unwrap()
+    }
+
+    fn download_with(
+        dir: &Path,
+        item: &DigitalItem,
+        options: &DownloadOptions,
+    ) -> Result<Download, BandsnatchError> {
+        Api::new(vec![]).download_item(
+            item,
+            dir.to_str().unwrap(),
+            "flac",
+            None,
+            &MultiProgress::new(),
+            options,
+        )
+    }
+
+    fn download(dir: &Path, item: &DigitalItem) -> Result<Download, BandsnatchError> {
+        download_with(dir, item, &DownloadOptions::default())
+    }
+
+    #[test]
+    fn download_removes_part_file_on_success() {
+        let dir = util::test_dir("download-success");
+        let url = serve_once(
+            "Content-Type: audio/flac\r\nContent-Disposition: attachment; filename=\"track.flac\"\r\nContent-Length: 15\r\n",
+            b"not really flac",
+        );
+
+        let download = download(&dir, &track(&url)).unwrap();
+
+        assert_eq!(download.bytes, 15);
+        assert_eq!(download.file, dir.join("track.flac"));
+        assert_eq!(fs::read(&download.file).unwrap(), b"not really flac");
+        assert!(!dir.join("track.flac.part").exists());
+    }
+
+    #[test]
+    fn download_does_not_trust_part_file_length() {
+        let dir = util::test_dir("download-full-part");
+        fs::write(dir.join("track.flac.part"), b"leftover chunks").unwrap();
+        let url = serve_once(
+            "Content-Type: audio/flac\r\nContent-Disposition: attachment; filename=\"track.flac\"\r\nContent-Length: 15\r\n",
+            b"not really flac",
+        );
+        let options = DownloadOptions {
+            resume: true,
+            ..Default::default()
+        };
+
+        let download = download_with(&dir, &track(&url), &options).unwrap();
+
+        assert_eq!(fs::read(&download.file).unwrap(), b"not really flac");
+    }
+
+    #[test]
+    fn download_without_content_length() {
+        let dir = util::test_dir("download-no-length");
+        let url = serve_once(
+            "Content-Type: audio/flac\r\nContent-Disposition: attachment; filename=\"track.flac\"\r\n",
+            b"not really flac",
+        );
+        let options = DownloadOptions {
+            resume: true,
+            ..Default::default()
+        };
+
+        let download = download_with(&dir, &track(&url), &options).unwrap();
+
+        assert_eq!(download.bytes, 15);
+        assert_eq!(fs::read(&download.file).unwrap(), b"not really flac");
+        assert!(!dir.join("track.flac.part").exists());
+    }
+
+    #[test]
+    fn download_removes_part_file_on_error() {
+        let dir = util::test_dir("download-error");
+        // The connection is closed partway through the body.
+        let url = serve_once(
+            "Content-Type: audio/flac\r\nContent-Disposition: attachment; filename=\"track.flac\"\r\nContent-Length: 100\r\n",
+            b"not really flac",
+        );
+
+        assert!(download(&dir, &track(&url)).is_err());
+
+        assert!(!dir.join("track.flac.part").exists());
+        assert!(!dir.join("track.flac").exists());
+    }
+
+    #[test]
+    fn parses_music_grid() {
+        let html = r#"<ol id="music-grid" data-client-items="[{&quot;id&quot;:3,&quot;type&quot;:&quot;track&quot;,&quot;title&quot;:&quot;Hidden&quot;,&quot;artist&quot;:null,&quot;page_url&quot;:&quot;/track/hidden&quot;}]">
+            <li data-item-id="album-1"><a href="/album/first"><p class="title">
+                First
+            </p></a></li>
+            <li data-item-id="album-2"><a href="https://other.example/album/second"><p class="title">
+                Second
+                <br><span class="artist-override">
+                    Someone Else
+                </span>
+            </p></a></li>
+        </ol>"#;
+
+        let items = parse_music_grid(html).unwrap();
+        let items = items
+            .iter()
+            .map(|i| {
+                (
+                    &*i.item_type,
+                    &*i.id,
+                    &*i.title,
+                    i.artist.as_deref(),
+                    &*i.page_url,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            items,
+            [
+                ("album", "1", "First", None, "/album/first"),
+                (
+                    "album",
+                    "2",
+                    "Second",
+                    Some("Someone Else"),
+                    "https://other.example/album/second"
+                ),
+                ("track", "3", "Hidden", None, "/track/hidden"),
+            ]
+        );
     }
 }
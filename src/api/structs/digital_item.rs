@@ -1,19 +1,31 @@
-use crate::util::make_string_fs_safe;
+use crate::util::{
+    make_path_component, GenreSource, UnicodeNorm, INDEPENDENT_LABEL, UNKNOWN_GENRE,
+};
 
-use chrono::{Datelike, NaiveDateTime};
-use serde::{self, Deserialize};
+use chrono::{DateTime, Datelike, NaiveDateTime, Utc};
+use serde::{self, Deserialize, Serialize};
+use serde_aux::prelude::deserialize_option_number_from_string;
 use std::{collections::HashMap, path::Path};
 
 const FORMAT: &str = "%d %b %Y %T %Z";
 
-// #[derive(Clone, Deserialize, Debug)]
-// #[serde(untagged)]
-// pub enum ArtId {
-//     Str(String),
-//     Num(i64),
-// }
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(untagged)]
+pub enum ArtId {
+    Str(String),
+    Num(i64),
+}
+
+impl std::fmt::Display for ArtId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArtId::Str(s) => write!(f, "{s}"),
+            ArtId::Num(n) => write!(f, "{n}"),
+        }
+    }
+}
 
-#[derive(Clone, Deserialize, Debug)]
+#[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct DigitalItem {
     pub downloads: Option<HashMap<String, DigitalItemDownload>>,
     pub package_release_date: Option<String>,
@@ -22,10 +34,97 @@ pub struct DigitalItem {
     pub download_type: Option<String>,
     pub download_type_str: String,
     pub item_type: String,
-    // pub art_id: Option<ArtId>,
+    pub art_id: Option<ArtId>,
+    /// When the release was last changed (e.g. remastered). Only present for
+    /// some releases.
+    #[serde(default, rename = "updated_at", alias = "mod_date")]
+    pub updated: Option<String>,
+    /// Tracks in the release, if Bandcamp includes them.
+    #[serde(default, deserialize_with = "deserialize_tracks")]
+    pub tracks: Vec<Track>,
+    /// Tags the release was given, which are mostly genres.
+    #[serde(default, deserialize_with = "deserialize_tags")]
+    pub tags: Vec<String>,
+    /// Set when `tracks` is only the start of a long tracklist.
+    #[serde(default)]
+    pub has_more_tracks: bool,
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub item_id: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub band_id: Option<u64>,
+    /// The label that put the release out, if it wasn't self-released.
+    #[serde(default)]
+    pub label: Option<String>,
+    /// Page the item was found on, for getting fresh download links.
+    #[serde(skip)]
+    pub download_page: Option<String>,
+    /// When the item was purchased, in the same format as
+    /// `package_release_date`. Filled in from the collection, if known.
+    #[serde(skip)]
+    pub purchased: Option<String>,
+}
+
+/// Details of a release from Bandcamp's mobile API, which always has the full
+/// tracklist.
+#[derive(Deserialize, Debug)]
+pub struct TralbumDetails {
+    #[serde(default, deserialize_with = "deserialize_tracks")]
+    pub tracks: Vec<Track>,
+}
+
+#[derive(Clone, Serialize, Debug)]
+pub struct Track {
+    pub title: String,
+    pub track_num: Option<u32>,
+    /// Length in seconds.
+    pub duration: Option<f64>,
 }
 
-#[derive(Clone, Deserialize, Debug)]
+/// Accept tracks either as plain titles or objects with a `title`, ignoring
+/// anything else rather than failing to parse the whole item.
+fn deserialize_tracks<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Track>, D::Error> {
+    let values = Option::<Vec<serde_json::Value>>::deserialize(deserializer)?;
+    Ok(values
+        .into_iter()
+        .flatten()
+        .filter_map(|v| match v {
+            serde_json::Value::String(title) => Some(Track {
+                title,
+                track_num: None,
+                duration: None,
+            }),
+            v => Some(Track {
+                title: v.get("title")?.as_str()?.to_string(),
+                track_num: v
+                    .get("track_num")
+                    .or_else(|| v.get("track_number"))
+                    .and_then(|n| n.as_u64())
+                    .map(|n| n as u32),
+                duration: v.get("duration").and_then(|d| d.as_f64()),
+            }),
+        })
+        .collect())
+}
+
+/// Accept tags either as plain names or objects with a `name`, ignoring
+/// anything else.
+fn deserialize_tags<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    let values = Option::<Vec<serde_json::Value>>::deserialize(deserializer)?;
+    Ok(values
+        .into_iter()
+        .flatten()
+        .filter_map(|v| match v {
+            serde_json::Value::String(name) => Some(name),
+            v => Some(v.get("name")?.as_str()?.to_string()),
+        })
+        .collect())
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct DigitalItemDownload {
     // pub size_mb: Option<String>,
     // pub description: String,
@@ -34,10 +133,12 @@ pub struct DigitalItemDownload {
 }
 
 impl DigitalItem {
-    // pub fn cover_url(&self) -> String {
-    //     let art_id = &self.art_id;
-    //     format!("https://f4.bcbits.com/img/a{art_id}")
-    // }
+    /// URL of the full resolution cover art, if the release has any.
+    pub fn cover_url(&self) -> Option<String> {
+        self.art_id
+            .as_ref()
+            .map(|art_id| format!("https://f4.bcbits.com/img/a{art_id}_0.jpg"))
+    }
 
     pub fn is_single(&self) -> bool {
         (self.download_type.is_some() && self.download_type.as_ref().unwrap() == "t")
@@ -45,29 +146,133 @@ impl DigitalItem {
             || self.item_type == "track"
     }
 
-    pub fn release_year(&self) -> String {
-        match &self.package_release_date {
-            Some(d) => match NaiveDateTime::parse_from_str(d, FORMAT) {
-                Ok(dt) => dt.and_utc().year().to_string(),
-                Err(err) => {
-                    debug!("Failed to parse date time: {}", err);
-                    String::from("0000")
-                },
-            },
-            None => String::from("0000"),
+    /// Keys of the formats this item can be downloaded in, sorted.
+    pub fn available_formats(&self) -> Vec<&str> {
+        let mut formats = self
+            .downloads
+            .iter()
+            .flat_map(|d| d.keys())
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        formats.sort_unstable();
+        formats
+    }
+
+    /// Parsed version of `updated`, if there is one.
+    pub fn updated_at(&self) -> Option<DateTime<Utc>> {
+        let updated = self.updated.as_deref()?;
+        NaiveDateTime::parse_from_str(updated, FORMAT)
+            .map(|dt| dt.and_utc())
+            .or_else(|_| DateTime::parse_from_rfc3339(updated).map(|dt| dt.with_timezone(&Utc)))
+            .ok()
+    }
+
+    /// The year the item was released in, falling back to the year it was
+    /// purchased in for releases without a (readable) release date, so that
+    /// they don't all end up as `0000`.
+    pub fn release_year_or_purchase_year(&self) -> String {
+        [&self.package_release_date, &self.purchased]
+            .into_iter()
+            .flatten()
+            .find_map(|d| NaiveDateTime::parse_from_str(d, FORMAT).ok())
+            .map_or_else(
+                || String::from("0000"),
+                |dt| dt.and_utc().year().to_string(),
+            )
+    }
+
+    /// Folder name for the release's genre, picked out of its tags by
+    /// `source`, lowercased and with spaces turned into hyphens. Releases
+    /// without any tags go in `UNKNOWN_GENRE`.
+    pub fn genre_dir(&self, source: GenreSource, norm: UnicodeNorm) -> String {
+        let tag = match source {
+            GenreSource::First => self.tags.first(),
+            GenreSource::Last => self.tags.last(),
+            // Reversed so that the first of equally specific tags wins.
+            GenreSource::MostSpecific => self
+                .tags
+                .iter()
+                .rev()
+                .max_by_key(|t| t.split_whitespace().count()),
+        };
+
+        let genre = tag
+            .map(|t| {
+                t.to_lowercase()
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join("-")
+            })
+            .unwrap_or_default();
+        if genre.is_empty() {
+            return String::from(UNKNOWN_GENRE);
         }
+        make_path_component(&genre, norm)
     }
 
-    pub fn destination_path<P: AsRef<Path>>(&self, root: P) -> String {
+    /// Folder name for the label that put the release out, or
+    /// `INDEPENDENT_LABEL` if it was self-released.
+    pub fn label_dir(&self, norm: UnicodeNorm) -> String {
+        match self.label.as_deref().map(str::trim) {
+            Some(label) if !label.is_empty() => make_path_component(label, norm),
+            _ => String::from(INDEPENDENT_LABEL),
+        }
+    }
+
+    pub fn destination_path<P: AsRef<Path>>(&self, root: P, norm: UnicodeNorm) -> String {
         root.as_ref()
-            .join(make_string_fs_safe(&self.artist))
+            .join(make_path_component(&self.artist, norm))
             .join(format!(
                 "{} ({})",
-                make_string_fs_safe(&self.title),
-                self.release_year()
+                make_path_component(&self.title, norm),
+                self.release_year_or_purchase_year()
             ))
             .to_str()
             .unwrap()
             .to_owned()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(release_date: Option<&str>, purchased: Option<&str>) -> DigitalItem {
+        let mut item: DigitalItem = serde_json::from_value(serde_json::json!({
+            "package_release_date": release_date,
+            "title": "Homogenic",
+            "artist": "Björk",
+            "download_type_str": "album",
+            "item_type": "album",
+        }))
+        .unwrap();
+        item.purchased = purchased.map(String::from);
+        item
+    }
+
+    #[test]
+    fn release_year_prefers_release_date() {
+        let item = item(
+            Some("22 Sep 1997 00:00:00 GMT"),
+            Some("30 Jan 2026 02:51:12 GMT"),
+        );
+        assert_eq!(item.release_year_or_purchase_year(), "1997");
+    }
+
+    #[test]
+    fn release_year_falls_back_to_purchase_year() {
+        let item = item(None, Some("30 Jan 2026 02:51:12 GMT"));
+        assert_eq!(item.release_year_or_purchase_year(), "2026");
+    }
+
+    #[test]
+    fn release_year_falls_back_on_unreadable_release_date() {
+        let item = item(Some("sometime in 1997"), Some("30 Jan 2026 02:51:12 GMT"));
+        assert_eq!(item.release_year_or_purchase_year(), "2026");
+    }
+
+    #[test]
+    fn release_year_without_any_date() {
+        assert_eq!(item(None, None).release_year_or_purchase_year(), "0000");
+    }
+}
@@ -1,21 +1,62 @@
-use serde::Deserialize;
-use serde_aux::prelude::deserialize_string_from_number;
+use serde::{de::IgnoredAny, Deserialize};
+use serde_aux::prelude::{deserialize_option_number_from_string, deserialize_string_from_number};
 use std::collections::HashMap;
 
 pub mod digital_item;
 pub use crate::api::structs::digital_item::DigitalItem;
 
+/// Where in a user's profile an item was found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ItemSource {
+    /// Purchased, so has a download page.
+    Collection,
+    /// Wished for, so `url` is the release's page rather than a download page.
+    Wishlist,
+    /// Put out by an artist or label the user follows, so like `Wishlist`,
+    /// `url` is the release's page.
+    Following,
+}
+
 /// Download URL with optional purchase date
 #[derive(Clone, Debug)]
 pub struct DownloadInfo {
     pub url: String,
     /// Date string of when the item was purchased (e.g., "30 Jan 2026 02:51:12 GMT")
     pub purchased: Option<String>,
+    /// The band or artist who released the item.
+    pub artist: String,
+    /// The name of the item.
+    pub title: String,
+    pub source: ItemSource,
+    /// Whether the item was pre-ordered, and so might not be out yet.
+    pub preorder: bool,
+    /// Date string of when the item is (or was) released, in the same format as
+    /// `purchased`.
+    pub release_date: Option<String>,
+    /// Whether `url` is a gift link, which stops working once it expires.
+    pub gift: bool,
+    /// The label that put the item out, if Bandcamp says.
+    pub label: Option<String>,
+    /// ID of the release, rather than of the purchase, so that buying the same
+    /// thing twice can be noticed.
+    pub release_id: Option<String>,
 }
 
 /// Map of item ID to download info (URL + purchase date)
 pub type DownloadsMap = HashMap<String, DownloadInfo>;
 
+/// A physical merch purchase, which has nothing to download.
+#[derive(Clone, Debug)]
+pub struct MerchItem {
+    /// The band or artist who sold the item.
+    pub artist: String,
+    /// The name of the item.
+    pub title: String,
+}
+
+/// Map of item ID to merch purchases.
+pub type MerchMap = HashMap<String, MerchItem>;
+
 /// Raw URL map as returned from Bandcamp API (before enriching with purchase dates)
 pub type RawDownloadsMap = HashMap<String, String>;
 
@@ -29,21 +70,34 @@ pub struct ParsedFanpageData {
     pub collection_data: CollectionData,
     /// Data about items in the user's music collection that have been hidden.
     pub hidden_data: CollectionData,
+    /// Data about items in the user's wishlist. Only present on the wishlist
+    /// page.
+    pub wishlist_data: Option<CollectionData>,
+    /// Data about the artists and labels the user follows. Only present on the
+    /// following page.
+    pub following_bands_data: Option<CollectionData>,
     pub item_cache: ItemCache,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct ItemCache {
+    #[serde(default)]
     pub collection: HashMap<String, Item>,
+    #[serde(default)]
+    pub wishlist: HashMap<String, WishlistItem>,
+    #[serde(default)]
+    pub following_bands: HashMap<String, FollowedBand>,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct Item {
-    //     /// Used in collection_data.sequence, and tracklist. Probably the most unique field?
-    //     #[serde(deserialize_with = "deserialize_string_from_number")]
-    //     pub item_id: String,
-    //     /// The type of the item: "album" or "track".
-    //     pub item_type: String,
+    /// ID of the release itself, which stays the same if it's bought more than
+    /// once.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub item_id: Option<u64>,
+    /// The type of the item: "album", "track", or "package" for merch.
+    #[serde(default)]
+    pub item_type: Option<String>,
     /// Used in `id => download url` mapping.
     pub sale_item_id: u64,
     /// Used in `id => download url` mapping, as the type of item (no idea what it means).
@@ -54,6 +108,84 @@ pub struct Item {
     pub item_title: String,
     /// Date string of when the item was purchased (e.g., "30 Jan 2026 02:51:12 GMT").
     pub purchased: Option<String>,
+    /// Whether the item was bought as a pre-order.
+    #[serde(default)]
+    pub is_preorder: bool,
+    /// Date string of when the item is released, in the same format as
+    /// `purchased`.
+    #[serde(default)]
+    pub release_date: Option<String>,
+    /// Set if the item was received as a gift.
+    #[serde(
+        default,
+        alias = "gift",
+        deserialize_with = "deserialize_option_number_from_string"
+    )]
+    pub gift_id: Option<u64>,
+    /// The label that put the item out, if it wasn't self-released.
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+impl Item {
+    /// `item_type` and `item_id` together, the same as the keys of wishlist
+    /// items.
+    pub fn release_id(&self) -> Option<String> {
+        let item_type = self.item_type.as_deref()?;
+        Some(format!("{item_type}{}", self.item_id?))
+    }
+
+    pub fn is_merch(&self) -> bool {
+        self.item_type.as_deref() == Some("package")
+    }
+}
+
+/// An item in a user's wishlist, which hasn't been purchased so doesn't have
+/// any sale info.
+#[derive(Deserialize, Debug)]
+pub struct WishlistItem {
+    #[serde(deserialize_with = "deserialize_string_from_number")]
+    pub item_id: String,
+    /// The type of the item: "album" or "track".
+    pub item_type: String,
+    /// The band or artist who released the item.
+    pub band_name: String,
+    /// The name of the item.
+    pub item_title: String,
+    /// Link to the item's page on Bandcamp.
+    pub item_url: String,
+}
+
+/// An artist or label that a user follows.
+#[derive(Deserialize, Debug)]
+pub struct FollowedBand {
+    pub name: String,
+    #[serde(default)]
+    pub url_hints: UrlHints,
+}
+
+/// Where an artist or label's pages are, either a subdomain of Bandcamp or
+/// their own domain.
+#[derive(Deserialize, Debug, Default)]
+pub struct UrlHints {
+    pub subdomain: Option<String>,
+    pub custom_domain: Option<String>,
+}
+
+/// A release listed on an artist or label's music page.
+#[derive(Deserialize, Debug)]
+pub struct BandMusicItem {
+    #[serde(deserialize_with = "deserialize_string_from_number")]
+    pub id: String,
+    /// The type of the item: "album" or "track".
+    #[serde(rename = "type")]
+    pub item_type: String,
+    pub title: String,
+    /// Only set when it's different to the artist or label whose page it is.
+    #[serde(default)]
+    pub artist: Option<String>,
+    /// Link to the release's page, usually relative to the music page.
+    pub page_url: String,
 }
 
 #[derive(Deserialize, Debug)]
@@ -80,13 +212,53 @@ pub struct ParsedCollectionItems {
     pub items: Vec<Item>,
 }
 
+/// Structure of the data returned from Bandcamp's wishlist API.
+#[derive(Deserialize, Debug)]
+pub struct ParsedWishlistItems {
+    pub more_available: bool,
+    pub last_token: String,
+    pub items: Vec<WishlistItem>,
+}
+
+/// Structure of the data returned from Bandcamp's following API.
+#[derive(Deserialize, Debug)]
+pub struct ParsedFollowingBands {
+    pub more_available: bool,
+    pub last_token: String,
+    // Not a typo on our part.
+    #[serde(alias = "followeers")]
+    pub followers: Vec<FollowedBand>,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct ParsedItemsData {
     pub digital_items: Vec<DigitalItem>,
 }
 
+/// Only the formats out of a download page's data blob, skipping everything
+/// else about the items.
+#[derive(Deserialize, Debug)]
+pub struct ParsedItemFormats {
+    pub digital_items: Vec<ItemFormats>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ItemFormats {
+    #[serde(default)]
+    pub downloads: Option<HashMap<String, IgnoredAny>>,
+}
+
 // #[derive(Deserialize, Debug)]
 // pub struct ParsedStatDownload {
 //     pub download_url: String,
 //     pub url: String,
 // }
+
+/// The parts of the `data-tralbum` blob on a release's page that we use.
+#[derive(Deserialize, Debug)]
+pub struct TralbumData {
+    /// Download page that anyone can use, set when the release is free to
+    /// download without an account or email address.
+    #[serde(default, rename = "freeDownloadPage")]
+    pub free_download_page: Option<String>,
+}
@@ -1,50 +1,517 @@
+use crate::error::BandsnatchError;
+use chrono::{DateTime, Utc};
+use fs2::FileExt;
+use rusqlite::{params, Connection};
+use serde::Serialize;
 use std::{
-    error::Error,
+    collections::HashSet,
     fs::{self, File},
-    io::Write,
-    path::Path,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
+/// Name of the cache file inside of the output folder.
+pub const CACHE_FILE: &str = "bandcamp-collection-downloader.cache";
+/// Name of the lock file inside of the output folder, held while running.
+pub const LOCK_FILE: &str = "bandcamp-collection-downloader.lock";
+/// Name of the SQLite cache database inside of the output folder.
+pub const CACHE_DB_FILE: &str = "bandsnatch-cache.sqlite";
+/// Name of the cache file for failed downloads inside of the output folder.
+pub const FAILED_CACHE_FILE: &str = "bandcamp-collection-downloader.failed";
+/// Name of the cache file for estimated download sizes inside of the output
+/// folder, labelled with the size in bytes.
+pub const SIZE_CACHE_FILE: &str = "bandsnatch-sizes.cache";
+/// Name of the cache file for where releases were downloaded to inside of the
+/// output folder, labelled with the release's folder relative to it.
+pub const PATH_CACHE_FILE: &str = "bandsnatch-paths.cache";
+
+/// A single row of the cache.
+#[derive(Clone, Debug, Serialize)]
+pub struct CacheEntry {
+    pub id: String,
+    pub label: String,
+    /// When the entry was added. Unknown for entries from older versions.
+    pub added_at: Option<DateTime<Utc>>,
+}
+
+impl CacheEntry {
+    /// Parse a cache line, also returning whether it's in the old format
+    /// without a timestamp column.
+    fn parse(line: &str) -> (Self, bool) {
+        let (id, rest) = line.split_once('|').unwrap_or((line, ""));
+        let rest = rest.strip_prefix(' ').unwrap_or(rest);
+
+        let (label, added_at, legacy) = match rest.rsplit_once('|') {
+            Some((label, ts)) if ts.trim().is_empty() => (label, None, false),
+            Some((label, ts)) => match DateTime::parse_from_rfc3339(ts.trim()) {
+                Ok(dt) => (label, Some(dt.with_timezone(&Utc)), false),
+                // Old format, where the label happened to have a `|` in it.
+                Err(_) => (rest, None, true),
+            },
+            None => (rest, None, true),
+        };
+
+        let entry = Self {
+            id: id.to_string(),
+            label: label.to_string(),
+            added_at,
+        };
+
+        (entry, legacy)
+    }
+
+    fn to_line(&self) -> String {
+        let added_at = self
+            .added_at
+            .map(|dt| dt.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+            .unwrap_or_default();
+        format!("{}| {}| {added_at}\n", self.id, self.label)
+    }
+}
+
 /// Cache for already downloaded/skipped songs, following the format set by
-/// Ezwen/bandcamp-collection-downloaderr.
+/// Ezwen/bandcamp-collection-downloaderr, with an extra column for when the
+/// entry was added. New entries are appended straight to the file, so nothing
+/// is lost if the program is killed partway through a run.
+#[derive(Debug)]
 pub struct Cache<P: AsRef<Path>> {
     path: P,
+    /// IDs in the file, so that checking for one doesn't mean reading it all
+    /// again.
+    ids: Mutex<HashSet<String>>,
+    /// Whether the file has been migrated yet, which waits until the first
+    /// write so that only reading the cache never changes it.
+    migrated: AtomicBool,
+}
+
+/// Take an exclusive lock on the output folder `root`, so that other instances
+/// don't use the same cache at the same time. Waits up to `timeout` for the
+/// lock, returning `None` if it couldn't be taken in time. The lock is released
+/// when the returned file is dropped.
+pub fn lock(root: &Path, timeout: Duration) -> io::Result<Option<File>> {
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(root.join(LOCK_FILE))?;
+    let start = Instant::now();
+
+    loop {
+        match file.try_lock_exclusive() {
+            Ok(()) => return Ok(Some(file)),
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() => {
+                if start.elapsed() >= timeout {
+                    return Ok(None);
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => return Err(e),
+        }
+    }
 }
 
-// TODO: move to something backed by sqlite or leveldb or similar, and add method to auto transform old format.
+/// Operations shared by all kinds of cache.
+pub trait CacheStore: Send {
+    fn entries(&self) -> Result<Vec<CacheEntry>, BandsnatchError>;
+
+    fn content(&self) -> Result<Vec<String>, BandsnatchError> {
+        Ok(self.entries()?.into_iter().map(|e| e.id).collect())
+    }
+
+    fn add(&self, id: &str, description: &str) -> Result<(), BandsnatchError>;
+
+    /// Add an entry only if not already present in the cache.
+    fn add_if_missing(&self, id: &str, description: &str) -> Result<(), BandsnatchError> {
+        if !self.content()?.contains(&id.to_string()) {
+            self.add(id, description)?;
+        }
+        Ok(())
+    }
+
+    /// Remove all entries with the given ID.
+    fn remove(&self, id: &str) -> Result<(), BandsnatchError> {
+        self.remove_batch(&[id])?;
+        Ok(())
+    }
+
+    /// Remove all entries with any of the given IDs, returning how many were
+    /// removed.
+    fn remove_batch(&self, ids: &[&str]) -> Result<usize, BandsnatchError>;
+}
+
+/// Open the main cache in `root`, either the text file or the SQLite
+/// database.
+pub fn open(root: &Path, sqlite: bool) -> Result<Box<dyn CacheStore>, BandsnatchError> {
+    let text_path = root.join(CACHE_FILE);
+    if sqlite {
+        Ok(Box::new(SqliteCache::open(
+            root.join(CACHE_DB_FILE),
+            Some(&text_path),
+        )?))
+    } else {
+        Ok(Box::new(Cache::new(text_path)))
+    }
+}
+
+/// IDs of all entries in the cache file `content`.
+fn ids_in(content: &str) -> HashSet<String> {
+    content
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(|l| CacheEntry::parse(l).0.id)
+        .collect()
+}
 
 impl<P: AsRef<Path>> Cache<P> {
     pub fn new(path: P) -> Self {
-        Self { path }
+        let cache = Self {
+            path,
+            ids: Mutex::new(HashSet::new()),
+            migrated: AtomicBool::new(false),
+        };
+        let tmp_path = cache.tmp_path();
+        if tmp_path.exists() {
+            warn!(
+                "Found {} left over from a failed write, removing it",
+                tmp_path.display()
+            );
+            let _ = fs::remove_file(tmp_path);
+        }
+
+        if let Ok(content) = fs::read_to_string(&cache.path) {
+            *cache.ids.lock().unwrap() = ids_in(&content);
+        }
+
+        cache
+    }
+
+    fn tmp_path(&self) -> PathBuf {
+        let mut tmp_path = self.path.as_ref().as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        PathBuf::from(tmp_path)
     }
 
-    pub fn content(&self) -> Result<Vec<String>, Box<dyn Error>> {
+    /// Replace the contents of the cache with `content`, writing it to a
+    /// temporary file first so that the cache is never left half written.
+    fn write(&self, content: &str) -> Result<(), BandsnatchError> {
+        let tmp_path = self.tmp_path();
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(&tmp_path, &self.path)?;
+        *self.ids.lock().unwrap() = ids_in(content);
+        Ok(())
+    }
+
+    /// Rewrite any entries in the old two column format to have an (empty)
+    /// timestamp column, so that they can't be confused for newer entries.
+    /// Also makes sure the file ends with a newline, so that entries can be
+    /// appended to it.
+    fn migrate(&self) -> Result<(), BandsnatchError> {
+        let Ok(content) = fs::read_to_string(&self.path) else {
+            return Ok(());
+        };
+        let entries = content
+            .lines()
+            .filter(|l| !l.is_empty())
+            .map(CacheEntry::parse)
+            .collect::<Vec<_>>();
+
+        if entries.iter().any(|(_, legacy)| *legacy) {
+            debug!("Migrating {} to new format", self.path.as_ref().display());
+            let migrated = entries.iter().map(|(e, _)| e.to_line()).collect::<String>();
+            self.write(&migrated)?;
+        } else if !content.is_empty() && !content.ends_with('\n') {
+            self.write(&format!("{content}\n"))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<P: AsRef<Path> + Send> CacheStore for Cache<P> {
+    fn entries(&self) -> Result<Vec<CacheEntry>, BandsnatchError> {
         if let Ok(content) = fs::read_to_string(&self.path) {
             Ok(content
                 .lines()
-                .map(|x| x.split('|').next().unwrap().to_string())
-                .collect::<Vec<String>>())
+                .filter(|l| !l.is_empty())
+                .map(|l| CacheEntry::parse(l).0)
+                .collect())
         } else {
             Ok(vec![])
         }
     }
 
-    pub fn add(&self, id: &str, description: &str) -> Result<(), Box<dyn Error>> {
-        let path = self.path.as_ref();
-        let mut file = File::options().create(true).append(true).open(path)?;
+    fn add(&self, id: &str, description: &str) -> Result<(), BandsnatchError> {
         // Format compatible with bandcamp-collection-downloader
-        let content = format!("{id}| {description}\n");
+        let entry = CacheEntry {
+            id: id.to_string(),
+            label: description.to_string(),
+            added_at: Some(Utc::now()),
+        };
 
-        file.write_all(content.as_bytes())?;
+        if !self.migrated.swap(true, Ordering::SeqCst) {
+            self.migrate()?;
+        }
+
+        // Held while writing, so that lines from different threads can't end
+        // up interleaved.
+        let mut ids = self.ids.lock().unwrap();
+        let mut file = File::options().create(true).append(true).open(&self.path)?;
+        file.write_all(entry.to_line().as_bytes())?;
+        file.sync_data()?;
+        ids.insert(entry.id);
 
         Ok(())
     }
 
-    /// Add an entry only if not already present in the cache.
-    pub fn add_if_missing(&self, id: &str, description: &str) -> Result<(), Box<dyn Error>> {
-        if !self.content()?.contains(&id.to_string()) {
+    fn add_if_missing(&self, id: &str, description: &str) -> Result<(), BandsnatchError> {
+        if !self.ids.lock().unwrap().contains(id) {
             self.add(id, description)?;
         }
         Ok(())
     }
+
+    fn remove_batch(&self, ids: &[&str]) -> Result<usize, BandsnatchError> {
+        let Ok(content) = fs::read_to_string(&self.path) else {
+            return Ok(0);
+        };
+
+        let mut removed = 0;
+        let kept = content
+            .lines()
+            .filter(|l| !l.is_empty())
+            .filter(|l| {
+                let remove = ids.contains(&CacheEntry::parse(l).0.id.as_str());
+                if remove {
+                    removed += 1;
+                }
+                !remove
+            })
+            .map(|l| format!("{l}\n"))
+            .collect::<String>();
+
+        if removed > 0 {
+            self.write(&kept)?;
+        }
+
+        Ok(removed)
+    }
+}
+
+/// Plain text record of what happened to each release during a run, in the
+/// same format as the cache so that the two can be diffed. Added to after
+/// each release, so it can be watched while running.
+#[derive(Debug)]
+pub struct StatusFile {
+    cache: Mutex<Cache<PathBuf>>,
+}
+
+impl StatusFile {
+    /// Start a new status file at `path`, replacing any old one.
+    pub fn create(path: PathBuf) -> Result<Self, BandsnatchError> {
+        let cache = Cache::new(path);
+        cache.write("")?;
+        Ok(Self {
+            cache: Mutex::new(cache),
+        })
+    }
+
+    pub fn record(&self, id: &str, status: &str) -> Result<(), BandsnatchError> {
+        self.cache.lock().unwrap().add(id, status)
+    }
+
+    /// Add a footer saying how many releases were recorded, once the run is
+    /// over.
+    pub fn finish(&self) -> Result<(), BandsnatchError> {
+        let cache = self.cache.lock().unwrap();
+        let count = cache.entries()?.len();
+        let mut content = fs::read_to_string(&cache.path)?;
+        content.push_str(&format!("DONE: {count} items\n"));
+        cache.write(&content)
+    }
+}
+
+/// Cache stored in a SQLite database, which is a lot quicker to check against
+/// than the text file once a collection gets big.
+pub struct SqliteCache {
+    conn: Connection,
+}
+
+impl SqliteCache {
+    /// Open the database at `path`, creating it if needed. If it doesn't exist
+    /// yet, entries are imported from the text cache at `legacy_path`.
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        legacy_path: Option<&Path>,
+    ) -> Result<Self, BandsnatchError> {
+        let path = path.as_ref();
+        let is_new = !path.exists();
+        let mut conn = Connection::open(path)?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS cache (id TEXT PRIMARY KEY, label TEXT, added_at TEXT)",
+            [],
+        )?;
+
+        if let Some(legacy_path) = legacy_path.filter(|p| is_new && p.exists()) {
+            let entries = Cache::new(legacy_path).entries()?;
+            debug!(
+                "Importing {} entries from {} into {}",
+                entries.len(),
+                legacy_path.display(),
+                path.display()
+            );
+
+            let tx = conn.transaction()?;
+            for entry in entries {
+                tx.execute(
+                    "INSERT OR IGNORE INTO cache (id, label, added_at) VALUES (?1, ?2, ?3)",
+                    params![
+                        entry.id,
+                        entry.label,
+                        entry.added_at.map(|dt| dt.to_rfc3339())
+                    ],
+                )?;
+            }
+            tx.commit()?;
+        }
+
+        Ok(Self { conn })
+    }
+}
+
+impl CacheStore for SqliteCache {
+    fn entries(&self) -> Result<Vec<CacheEntry>, BandsnatchError> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, label, added_at FROM cache ORDER BY rowid")?;
+        let entries = stmt
+            .query_map([], |row| {
+                let added_at = row
+                    .get::<_, Option<String>>(2)?
+                    .and_then(|ts| DateTime::parse_from_rfc3339(&ts).ok())
+                    .map(|dt| dt.with_timezone(&Utc));
+                Ok(CacheEntry {
+                    id: row.get(0)?,
+                    label: row.get(1)?,
+                    added_at,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(entries)
+    }
+
+    fn content(&self) -> Result<Vec<String>, BandsnatchError> {
+        let mut stmt = self.conn.prepare("SELECT id FROM cache")?;
+        let ids = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ids)
+    }
+
+    fn add(&self, id: &str, description: &str) -> Result<(), BandsnatchError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO cache (id, label, added_at) VALUES (?1, ?2, ?3)",
+            params![id, description, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    fn add_if_missing(&self, id: &str, description: &str) -> Result<(), BandsnatchError> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO cache (id, label, added_at) VALUES (?1, ?2, ?3)",
+            params![id, description, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    fn remove_batch(&self, ids: &[&str]) -> Result<usize, BandsnatchError> {
+        let mut removed = 0;
+        for id in ids {
+            removed += self
+                .conn
+                .execute("DELETE FROM cache WHERE id = ?1", params![id])?;
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util;
+
+    const CONTENT: &str = "a1| First| 2026-01-01T00:00:00Z\na2| Second| 2026-01-02T00:00:00Z\n";
+
+    fn ids(cache: &Cache<PathBuf>) -> Vec<String> {
+        cache.content().unwrap()
+    }
+
+    #[test]
+    fn failed_write_leaves_cache_intact() {
+        let dir = util::test_dir("cache-failed-write");
+        let path = dir.join(CACHE_FILE);
+        fs::write(&path, CONTENT).unwrap();
+        let cache = Cache::new(path.clone());
+
+        // Something in the way of the temporary file makes writing it fail
+        // partway through rewriting the cache.
+        fs::create_dir(cache.tmp_path()).unwrap();
+        assert!(cache.remove("a1").is_err());
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), CONTENT);
+        assert_eq!(ids(&cache), ["a1", "a2"]);
+    }
+
+    #[test]
+    fn leftover_tmp_file_is_removed_on_open() {
+        let dir = util::test_dir("cache-leftover-tmp");
+        let path = dir.join(CACHE_FILE);
+        fs::write(&path, CONTENT).unwrap();
+        // What a crash halfway through writing would leave behind.
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, "a1| Fir").unwrap();
+
+        let cache = Cache::new(path.clone());
+
+        assert!(!Path::new(&tmp_path).exists());
+        assert_eq!(ids(&cache), ["a1", "a2"]);
+    }
+
+    /// Set to the folder to use when `appended_entries_survive_abort` runs
+    /// itself in a child process.
+    const ABORT_DIR_VAR: &str = "BANDSNATCH_TEST_ABORT_DIR";
+
+    #[test]
+    fn appended_entries_survive_abort() {
+        if let Some(dir) = std::env::var_os(ABORT_DIR_VAR) {
+            let cache = Cache::new(Path::new(&dir).join(CACHE_FILE));
+            cache.add("a1", "First").unwrap();
+            cache.add_if_missing("a2", "Second").unwrap();
+            // Nothing gets a chance to be flushed or dropped.
+            std::process::abort();
+        }
+
+        let dir = util::test_dir("cache-abort");
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "cache::tests::appended_entries_survive_abort"])
+            .env(ABORT_DIR_VAR, &dir)
+            .output()
+            .unwrap()
+            .status;
+        assert!(!status.success());
+
+        let cache = Cache::new(dir.join(CACHE_FILE));
+        assert_eq!(ids(&cache), ["a1", "a2"]);
+    }
 }
@@ -1,3 +1,8 @@
+pub mod cache;
+pub mod completions;
+pub mod cookies;
 pub mod debug_collection;
+pub mod export;
+pub mod list_formats;
 pub mod release;
 pub mod run;
@@ -0,0 +1,486 @@
+use crate::{
+    api,
+    cache::{self, CacheStore},
+    cookies, util,
+};
+use chrono::{DateTime, Utc};
+use clap::{Args as ClapArgs, Subcommand};
+use regex::Regex;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::Duration,
+};
+
+/// Look at or manage the cache of downloaded releases.
+#[derive(Debug, ClapArgs)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Debug, Subcommand)]
+enum Commands {
+    /// Print the contents of the cache.
+    Inspect(InspectArgs),
+    /// Remove releases from the cache that are no longer in your collection.
+    Prune(PruneArgs),
+    /// Print a summary of what's in the cache.
+    Stats(StatsArgs),
+    /// Check that releases in the cache actually exist in the output folder.
+    Verify(VerifyArgs),
+}
+
+#[derive(Debug, ClapArgs)]
+struct InspectArgs {
+    /// The folder the cache is in.
+    #[arg(
+        short,
+        long = "output-folder",
+        value_name = "FOLDER",
+        default_value = "./",
+        env = "BS_OUTPUT_FOLDER"
+    )]
+    output_folder: String,
+
+    /// Print the cache as a JSON array.
+    #[arg(long)]
+    json: bool,
+
+    /// Use the SQLite cache instead of the text file.
+    #[arg(long = "sqlite-cache", env = "BS_SQLITE_CACHE")]
+    sqlite_cache: bool,
+}
+
+#[derive(Debug, ClapArgs)]
+struct StatsArgs {
+    /// The folder the cache is in.
+    #[arg(
+        short,
+        long = "output-folder",
+        value_name = "FOLDER",
+        default_value = "./",
+        env = "BS_OUTPUT_FOLDER"
+    )]
+    output_folder: String,
+
+    /// Print the stats as a JSON object.
+    #[arg(long)]
+    json: bool,
+
+    /// Use the SQLite cache instead of the text file.
+    #[arg(long = "sqlite-cache", env = "BS_SQLITE_CACHE")]
+    sqlite_cache: bool,
+}
+
+#[derive(Debug, ClapArgs)]
+struct VerifyArgs {
+    /// Remove releases that are missing from the cache, so that they get
+    /// downloaded again on the next run.
+    #[arg(long)]
+    fix: bool,
+
+    /// Also let `--fix` remove releases downloaded before bandsnatch kept
+    /// track of where they went, which are looked for in the default folder
+    /// layout. Only use this if they were downloaded without any options that
+    /// change the layout, like `--path-template`, or they'll all be removed.
+    #[arg(long = "assume-default-layout", requires = "fix")]
+    assume_default_layout: bool,
+
+    /// The folder the cache is in.
+    #[arg(
+        short,
+        long = "output-folder",
+        value_name = "FOLDER",
+        default_value = "./",
+        env = "BS_OUTPUT_FOLDER"
+    )]
+    output_folder: String,
+
+    /// Use the SQLite cache instead of the text file.
+    #[arg(long = "sqlite-cache", env = "BS_SQLITE_CACHE")]
+    sqlite_cache: bool,
+
+    /// Unicode normalization form that was used for names in paths.
+    #[arg(
+        long = "unicode-norm",
+        value_enum,
+        value_name = "FORM",
+        default_value_t = util::UnicodeNorm::Nfc,
+        env = "BS_UNICODE_NORM"
+    )]
+    unicode_norm: util::UnicodeNorm,
+}
+
+#[derive(Debug, Serialize)]
+struct Stats {
+    total: usize,
+    first_added: Option<DateTime<Utc>>,
+    last_added: Option<DateTime<Utc>>,
+    /// Counts of what happened to each release, going by its label.
+    by_status: BTreeMap<&'static str, usize>,
+    /// Counts of albums and tracks, going by their ID.
+    by_type: BTreeMap<&'static str, usize>,
+}
+
+/// Guess what happened to a release from the label it was cached with.
+fn entry_status(entry: &cache::CacheEntry) -> &'static str {
+    match entry.label.as_str() {
+        "UNKNOWN" => "unknown",
+        "No downloads" => "no downloads",
+        "MERCH" => "merch",
+        l if l.starts_with("Skipped") => "skipped",
+        _ => "downloaded",
+    }
+}
+
+fn entry_type(entry: &cache::CacheEntry) -> &'static str {
+    match entry.id.chars().next() {
+        Some('a') => "album",
+        Some('t') => "track",
+        Some('p') => "merch",
+        _ => "other",
+    }
+}
+
+#[derive(Debug, ClapArgs)]
+struct PruneArgs {
+    /// Talk to Bandcamp at this URL instead of `https://bandcamp.com`, e.g. for
+    /// a self-hosted instance.
+    #[arg(
+        long = "api-base-url",
+        value_name = "URL",
+        value_parser = api::parse_base_url,
+        env = "BS_API_BASE_URL"
+    )]
+    api_base_url: Option<String>,
+
+    /// Only use cookies set for this domain (and its subdomains), e.g. for a
+    /// self-hosted instance.
+    #[arg(
+        long = "cookie-domain",
+        value_name = "DOMAIN",
+        default_value = cookies::DEFAULT_DOMAIN,
+        env = "BS_COOKIE_DOMAIN"
+    )]
+    cookie_domain: String,
+
+    #[arg(
+        short,
+        long,
+        value_name = "COOKIES_FILE",
+        value_delimiter = ',',
+        env = "BS_COOKIES"
+    )]
+    cookies: Vec<String>,
+
+    /// Print what would be removed, without actually removing it.
+    #[arg(short = 'd', long = "dry-run")]
+    dry_run: bool,
+
+    /// The folder the cache is in.
+    #[arg(
+        short,
+        long = "output-folder",
+        value_name = "FOLDER",
+        default_value = "./",
+        env = "BS_OUTPUT_FOLDER"
+    )]
+    output_folder: String,
+
+    /// Use the SQLite cache instead of the text file.
+    #[arg(long = "sqlite-cache", env = "BS_SQLITE_CACHE")]
+    sqlite_cache: bool,
+
+    /// Name of the user whose collection to compare against (must be logged in
+    /// through cookies).
+    #[clap(env = "BS_USER")]
+    user: String,
+}
+
+/// Split a cache ID into the user it was added for with
+/// `--per-user-subfolder`, the release's own ID, and the format it was added
+/// with by `--format-dir`.
+fn split_id(id: &str) -> (Option<&str>, &str, Option<&str>) {
+    let (user, id) = match id.split_once("::") {
+        Some((user, id)) => (Some(user), id),
+        None => (None, id),
+    };
+    match id.split_once(':') {
+        Some((id, format)) => (user, id, Some(format)),
+        None => (user, id, None),
+    }
+}
+
+/// Take the lock on the output folder `root`, so that a run can't add to the
+/// cache while it's being changed. Held until the returned file is dropped.
+fn lock(root: &Path) -> Result<fs::File, Box<dyn std::error::Error>> {
+    cache::lock(root, Duration::ZERO)?.ok_or_else(|| {
+        format!(
+            "Another instance of Bandsnatch is already using `{}`, try again once it's finished.",
+            root.display()
+        )
+        .into()
+    })
+}
+
+/// Whether the cache entry `id` could have come from a user's collection,
+/// rather than their wishlist or the artists they follow, which are keyed by
+/// the release's own type and ID.
+fn from_collection(id: &str) -> bool {
+    !id.starts_with("album") && !id.starts_with("track")
+}
+
+fn open_cache(
+    output_folder: &str,
+    sqlite: bool,
+) -> Result<Box<dyn CacheStore>, Box<dyn std::error::Error>> {
+    let root = shellexpand::tilde(output_folder);
+    Ok(cache::open(Path::new(root.as_ref()), sqlite)?)
+}
+
+pub fn command(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    match args.command {
+        Commands::Inspect(args) => inspect(args),
+        Commands::Prune(args) => prune(args),
+        Commands::Stats(args) => stats(args),
+        Commands::Verify(args) => verify(args),
+    }
+}
+
+fn inspect(args: InspectArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let entries = open_cache(&args.output_folder, args.sqlite_cache)?.entries()?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+
+    for entry in &entries {
+        let added_at = entry
+            .added_at
+            .map(|dt| dt.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_else(|| String::from("unknown"));
+        println!("{:<12} {:<16} {}", entry.id, added_at, entry.label);
+    }
+    println!("\n{} entries in {}", entries.len(), args.output_folder);
+
+    Ok(())
+}
+
+fn prune(args: PruneArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let cookies_files = args
+        .cookies
+        .iter()
+        .map(|p| shellexpand::tilde(p).into_owned())
+        .collect::<Vec<_>>();
+    let cookies = cookies::get_domain_cookies(&cookies_files, &args.cookie_domain)?;
+    let api = api::Api::with_options(
+        cookies,
+        api::ClientOptions {
+            base_url: args.api_base_url.clone(),
+            ..Default::default()
+        },
+    );
+
+    let root = shellexpand::tilde(&args.output_folder);
+    // Held until this function returns.
+    let _lock = (!args.dry_run)
+        .then(|| lock(Path::new(root.as_ref())))
+        .transpose()?;
+    let cache = open_cache(&args.output_folder, args.sqlite_cache)?;
+    let entries = cache.entries()?;
+
+    // Entries added with `--per-user-subfolder` are checked against the
+    // collection of the user they were added for.
+    let mut users = entries
+        .iter()
+        .filter_map(|e| split_id(&e.id).0.map(String::from))
+        .collect::<HashSet<_>>();
+    users.insert(args.user.clone());
+    let mut collections = HashMap::new();
+    for user in users {
+        let page = api.get_download_urls(&user, None, None, None)?;
+        let mut collection = page.download_urls;
+        collection.extend(page.gifts);
+        collections.insert(user, collection);
+    }
+
+    let stale = entries
+        .into_iter()
+        // Merch doesn't show up with the downloads.
+        .filter(|e| e.label != "MERCH")
+        .filter(|e| {
+            let (user, id, _) = split_id(&e.id);
+            // Wishlisted and followed releases come and go without ever being
+            // bought, so there's nothing to compare them against.
+            from_collection(id) && !collections[user.unwrap_or(&args.user)].contains_key(id)
+        })
+        .collect::<Vec<_>>();
+
+    for entry in &stale {
+        println!("{}| {}", entry.id, entry.label);
+    }
+
+    if args.dry_run {
+        println!("\nWould remove {} entries", stale.len());
+        return Ok(());
+    }
+
+    let ids = stale.iter().map(|e| e.id.as_str()).collect::<Vec<_>>();
+    let removed = cache.remove_batch(&ids)?;
+    println!("\nRemoved {removed} entries");
+
+    Ok(())
+}
+
+fn stats(args: StatsArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let entries = open_cache(&args.output_folder, args.sqlite_cache)?.entries()?;
+
+    let mut stats = Stats {
+        total: entries.len(),
+        first_added: entries.iter().filter_map(|e| e.added_at).min(),
+        last_added: entries.iter().filter_map(|e| e.added_at).max(),
+        by_status: BTreeMap::new(),
+        by_type: BTreeMap::new(),
+    };
+    for entry in &entries {
+        *stats.by_status.entry(entry_status(entry)).or_default() += 1;
+        *stats.by_type.entry(entry_type(entry)).or_default() += 1;
+    }
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+
+    let format_date = |dt: Option<DateTime<Utc>>| {
+        dt.map(|dt| dt.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_else(|| String::from("unknown"))
+    };
+    println!("{:<16} {}", "Entries", stats.total);
+    println!("{:<16} {}", "First added", format_date(stats.first_added));
+    println!("{:<16} {}", "Last added", format_date(stats.last_added));
+    println!();
+    for (status, count) in &stats.by_status {
+        println!("{status:<16} {count}");
+    }
+    println!();
+    for (kind, count) in &stats.by_type {
+        println!("{kind:<16} {count}");
+    }
+
+    Ok(())
+}
+
+/// Work out where a release was downloaded to from its cache entry, assuming
+/// that the default folder layout was used. Returns `None` for entries that
+/// weren't downloaded, or have labels from other tools. Only needed for entries
+/// from before `PATH_CACHE_FILE` was kept.
+fn expected_path(
+    root: &Path,
+    entry: &cache::CacheEntry,
+    norm: util::UnicodeNorm,
+) -> Option<PathBuf> {
+    static LABEL: OnceLock<Regex> = OnceLock::new();
+    let label = LABEL.get_or_init(|| Regex::new(r"^(.*) \((\d{4})\) by (.*)$").unwrap());
+    let captures = label.captures(&entry.label)?;
+    let (title, year, artist) = (&captures[1], &captures[2], &captures[3]);
+
+    let (user, _, format) = split_id(&entry.id);
+    // Added with `--per-user-subfolder`.
+    let root = match user {
+        Some(user) => root.join(util::make_path_component(user, norm)),
+        None => root.to_path_buf(),
+    };
+
+    let path = root
+        .join(util::make_path_component(artist, norm))
+        .join(format!(
+            "{} ({year})",
+            util::make_path_component(title, norm)
+        ));
+
+    // Added with `--format-dir`. Which format `--lossless-only` picked isn't
+    // known, so the release's folder is checked instead.
+    match format {
+        Some(format) if format != "lossless" => Some(path.join(format)),
+        _ => Some(path),
+    }
+}
+
+fn verify(args: VerifyArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let root = shellexpand::tilde(&args.output_folder);
+    let root = Path::new(root.as_ref());
+    // Held until this function returns.
+    let _lock = args.fix.then(|| lock(root)).transpose()?;
+    let cache = open_cache(&args.output_folder, args.sqlite_cache)?;
+    let path_cache = cache::Cache::new(root.join(cache::PATH_CACHE_FILE));
+    let paths = path_cache
+        .entries()?
+        .into_iter()
+        .map(|e| (e.id, root.join(e.label)))
+        .collect::<HashMap<_, _>>();
+
+    let mut checked = 0;
+    let mut broken = vec![];
+    // Missing from where the default layout would have put them, which might
+    // just mean they were put somewhere else.
+    let mut guessed = vec![];
+    for entry in cache.entries()? {
+        let (path, known) = match paths.get(&entry.id) {
+            Some(path) => (path.clone(), true),
+            None => match expected_path(root, &entry, args.unicode_norm) {
+                Some(path) => (path, false),
+                None => continue,
+            },
+        };
+        checked += 1;
+
+        let is_empty = fs::read_dir(&path).map_or(true, |mut d| d.next().is_none());
+        if !is_empty {
+            continue;
+        }
+        if known {
+            println!("{}| {} (missing {})", entry.id, entry.label, path.display());
+            broken.push(entry.id);
+        } else {
+            println!(
+                "{}| {} (missing {}, if it was downloaded in the default layout)",
+                entry.id,
+                entry.label,
+                path.display()
+            );
+            guessed.push(entry.id);
+        }
+    }
+
+    println!(
+        "\nChecked {checked} releases, {} missing or empty",
+        broken.len() + guessed.len()
+    );
+    if !args.fix {
+        return Ok(());
+    }
+    if args.assume_default_layout {
+        broken.append(&mut guessed);
+    } else if !guessed.is_empty() {
+        println!(
+            "Left {} entries from before where releases went was kept track of, use `--assume-default-layout` to remove them too",
+            guessed.len()
+        );
+    }
+    if broken.is_empty() {
+        return Ok(());
+    }
+
+    let ids = broken.iter().map(String::as_str).collect::<Vec<_>>();
+    let removed = cache.remove_batch(&ids)?;
+    path_cache.remove_batch(&ids)?;
+    println!("Removed {removed} entries, they'll be downloaded again on the next run");
+
+    Ok(())
+}
@@ -0,0 +1,18 @@
+use clap::{Args as ClapArgs, Command};
+use clap_complete::Shell;
+use std::io;
+
+/// Print a completion script for your shell.
+#[derive(Debug, ClapArgs)]
+pub struct Args {
+    /// Shell to generate completions for.
+    #[arg(value_enum)]
+    shell: Shell,
+}
+
+/// Write completions for all of `cmd`'s subcommands and options to stdout.
+pub fn command(Args { shell }: Args, mut cmd: Command) -> Result<(), Box<dyn std::error::Error>> {
+    let name = cmd.get_name().to_string();
+    clap_complete::generate(shell, &mut cmd, name, &mut io::stdout());
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use crate::{api, cookies};
+use clap::{Args as ClapArgs, Subcommand};
+
+/// Work with the cookies used to log in to Bandcamp.
+#[derive(Debug, ClapArgs)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Debug, Subcommand)]
+enum Commands {
+    /// Check that your cookies are still logged in, exiting with 1 if not.
+    Check(CheckArgs),
+}
+
+#[derive(Debug, ClapArgs)]
+struct CheckArgs {
+    /// Talk to Bandcamp at this URL instead of `https://bandcamp.com`, e.g. for
+    /// a self-hosted instance.
+    #[arg(
+        long = "api-base-url",
+        value_name = "URL",
+        value_parser = api::parse_base_url,
+        env = "BS_API_BASE_URL"
+    )]
+    api_base_url: Option<String>,
+
+    /// Only use cookies set for this domain (and its subdomains).
+    #[arg(
+        long = "cookie-domain",
+        value_name = "DOMAIN",
+        default_value = cookies::DEFAULT_DOMAIN,
+        env = "BS_COOKIE_DOMAIN"
+    )]
+    cookie_domain: String,
+
+    #[arg(
+        short,
+        long,
+        value_name = "COOKIES_FILE",
+        value_delimiter = ',',
+        env = "BS_COOKIES"
+    )]
+    cookies: Vec<String>,
+
+    /// Read cookies straight from a browser you're logged in with, instead of
+    /// a cookies file.
+    #[arg(
+        long = "cookies-from-browser",
+        value_enum,
+        value_name = "BROWSER",
+        conflicts_with = "cookies",
+        env = "BS_COOKIES_FROM_BROWSER"
+    )]
+    cookies_from_browser: Option<cookies::Browser>,
+
+    /// Read cookies from an environment variable, formatted like a `Cookie`
+    /// header.
+    #[arg(
+        long = "cookies-from-env",
+        value_name = "VAR",
+        conflicts_with_all = ["cookies", "cookies_from_browser"],
+        env = "BS_COOKIES_FROM_ENV"
+    )]
+    cookies_from_env: Option<String>,
+}
+
+pub fn command(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    match args.command {
+        Commands::Check(args) => check(args),
+    }
+}
+
+fn check(args: CheckArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let cookies_files = args
+        .cookies
+        .iter()
+        .map(|p| shellexpand::tilde(p).into_owned())
+        .collect::<Vec<_>>();
+    let cookies = cookies::load(
+        args.cookies_from_browser,
+        args.cookies_from_env.as_deref(),
+        &cookies_files,
+        &args.cookie_domain,
+    )?;
+    let expiry = cookies::login_expiry(&cookies);
+    for warning in cookies::check_expiry(&cookies) {
+        warn!("{warning}");
+    }
+
+    let api = api::Api::with_options(
+        cookies,
+        api::ClientOptions {
+            base_url: args.api_base_url.clone(),
+            ..Default::default()
+        },
+    );
+    let Some(username) = api.check_login()? else {
+        error!("Cookies aren't logged in to Bandcamp, they may have expired. Try exporting them again.");
+        std::process::exit(1);
+    };
+
+    println!("Logged in as {username}");
+    if let Some(expiry) = expiry {
+        println!("Login expires on {}", expiry.format("%Y-%m-%d %H:%M UTC"));
+    }
+
+    Ok(())
+}
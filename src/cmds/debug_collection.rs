@@ -9,8 +9,14 @@ use std::io::Write;
 /// Bandcamp.
 #[derive(Debug, ClapArgs)]
 pub struct Args {
-    #[arg(short, long, value_name = "COOKIES_FILE", env = "BS_COOKIES")]
-    cookies: Option<String>,
+    #[arg(
+        short,
+        long,
+        value_name = "COOKIES_FILE",
+        value_delimiter = ',',
+        env = "BS_COOKIES"
+    )]
+    cookies: Vec<String>,
 
     /// Name of the user to grab the profile of.
     #[arg(short, long, env = "BS_USER")]
@@ -35,17 +41,17 @@ pub fn command(
         save,
     }: Args,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let cookies_file = cookies.map(|p| {
-        let expanded = shellexpand::tilde(&p);
-        expanded.into_owned()
-    });
+    let cookies_files = cookies
+        .iter()
+        .map(|p| shellexpand::tilde(p).into_owned())
+        .collect::<Vec<_>>();
 
-    let cookies = cookies::get_bandcamp_cookies(cookies_file.as_deref())?;
+    let cookies = cookies::get_bandcamp_cookies(&cookies_files)?;
     let api = crate::api::Api::new(cookies);
 
     let body = api
         .client
-        .get(&format!("https://bandcamp.com/{user}"))
+        .get(format!("https://bandcamp.com/{user}"))
         .send()?
         .text()?;
     let soup = Soup::new(&body);
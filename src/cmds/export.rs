@@ -0,0 +1,177 @@
+use crate::{api, cookies, util};
+use clap::Args as ClapArgs;
+use crossbeam_utils::thread;
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::{self, Write},
+    sync::Mutex,
+};
+
+/// Write details of every release in your collection to a CSV file, without
+/// downloading anything.
+#[derive(Debug, ClapArgs)]
+pub struct Args {
+    /// Only look at releases with a title matching this regex.
+    #[arg(long, value_name = "PATTERN", value_parser = util::parse_filter, env = "BS_ALBUM")]
+    album: Option<String>,
+
+    /// Only look at releases by an artist matching this regex.
+    #[arg(long, value_name = "PATTERN", value_parser = util::parse_filter, env = "BS_ARTIST")]
+    artist: Option<String>,
+
+    /// Talk to Bandcamp at this URL instead of `https://bandcamp.com`, e.g. for
+    /// a self-hosted instance.
+    #[arg(
+        long = "api-base-url",
+        value_name = "URL",
+        value_parser = api::parse_base_url,
+        env = "BS_API_BASE_URL"
+    )]
+    api_base_url: Option<String>,
+
+    /// Match `--album` and `--artist` case sensitively.
+    #[arg(long = "case-sensitive", env = "BS_CASE_SENSITIVE")]
+    case_sensitive: bool,
+
+    #[arg(
+        short,
+        long,
+        value_name = "COOKIES_FILE",
+        value_delimiter = ',',
+        env = "BS_COOKIES"
+    )]
+    cookies: Vec<String>,
+
+    /// Only use cookies set for this domain (and its subdomains), e.g. for a
+    /// self-hosted instance.
+    #[arg(
+        long = "cookie-domain",
+        value_name = "DOMAIN",
+        default_value = cookies::DEFAULT_DOMAIN,
+        env = "BS_COOKIE_DOMAIN"
+    )]
+    cookie_domain: String,
+
+    /// Enables some extra debug output in certain scenarios.
+    #[arg(long, env = "BS_DEBUG")]
+    debug: bool,
+
+    /// The amount of releases to look up at once.
+    #[arg(short, long, default_value_t = 4, env = "BS_JOBS")]
+    jobs: u8,
+
+    /// Maximum number of releases to export.
+    #[arg(short = 'n', long, env = "BS_LIMIT")]
+    limit: Option<usize>,
+
+    /// File to write the CSV to, instead of stdout.
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<String>,
+
+    /// Name of the user to export the collection of (must be logged in
+    /// through cookies).
+    #[clap(env = "BS_USER")]
+    user: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Row {
+    id: String,
+    title: String,
+    artist: String,
+    year: String,
+    is_single: bool,
+    purchased: Option<String>,
+    /// Separated by `;`.
+    available_formats: String,
+}
+
+pub fn command(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let cookies_files = args
+        .cookies
+        .iter()
+        .map(|p| shellexpand::tilde(p).into_owned())
+        .collect::<Vec<_>>();
+    let cookies = cookies::get_domain_cookies(&cookies_files, &args.cookie_domain)?;
+    let api = api::Api::with_options(
+        cookies,
+        api::ClientOptions {
+            base_url: args.api_base_url.clone(),
+            ..Default::default()
+        },
+    );
+    let album = args
+        .album
+        .as_deref()
+        .map(|p| util::build_filter(p, args.case_sensitive))
+        .transpose()?;
+    let artist = args
+        .artist
+        .as_deref()
+        .map(|p| util::build_filter(p, args.case_sensitive))
+        .transpose()?;
+
+    let mut items = api
+        .get_download_urls(&args.user, artist.as_ref(), album.as_ref(), None)?
+        .download_urls
+        .into_iter()
+        .collect::<Vec<_>>();
+    items.sort_by(|(a, _), (b, _)| a.cmp(b));
+    items.truncate(args.limit.unwrap_or(usize::MAX));
+
+    let queue = util::WorkQueue::from_vec(items);
+    let rows = Mutex::new(Vec::<Row>::new());
+
+    thread::scope(|scope| {
+        for _ in 0..args.jobs {
+            let api = &api;
+            let queue = queue.clone();
+            let rows = &rows;
+
+            scope.spawn(move |_| {
+                while let Some((id, info)) = queue.get_work() {
+                    let mut item = match api.get_digital_item(&info.url, &args.debug) {
+                        Ok(Some(item)) => item,
+                        Ok(None) => {
+                            warn!("Could not find digital item for {id}");
+                            continue;
+                        }
+                        Err(e) => {
+                            warn!("Failed to get details for {id}: {e}");
+                            continue;
+                        }
+                    };
+
+                    item.purchased = info.purchased.clone();
+                    rows.lock().unwrap().push(Row {
+                        id,
+                        year: item.release_year_or_purchase_year(),
+                        is_single: item.is_single(),
+                        purchased: info.purchased,
+                        available_formats: item.available_formats().join(";"),
+                        title: item.title,
+                        artist: item.artist,
+                    });
+                }
+            });
+        }
+    })
+    .unwrap();
+
+    // Threads finish in whatever order, so make the output stable.
+    let mut rows = rows.into_inner().unwrap();
+    rows.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(shellexpand::tilde(path).as_ref())?),
+        None => Box::new(io::stdout()),
+    };
+    let mut writer = csv::Writer::from_writer(output);
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
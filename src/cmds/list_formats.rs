@@ -0,0 +1,102 @@
+use crate::{api, cookies, util};
+use clap::Args as ClapArgs;
+
+/// List which formats each release in your collection can be downloaded in.
+#[derive(Debug, ClapArgs)]
+pub struct Args {
+    /// Only look at releases with a title matching this regex.
+    #[arg(long, value_name = "PATTERN", value_parser = util::parse_filter, env = "BS_ALBUM")]
+    album: Option<String>,
+
+    /// Only look at releases by an artist matching this regex.
+    #[arg(long, value_name = "PATTERN", value_parser = util::parse_filter, env = "BS_ARTIST")]
+    artist: Option<String>,
+
+    /// Talk to Bandcamp at this URL instead of `https://bandcamp.com`, e.g. for
+    /// a self-hosted instance.
+    #[arg(
+        long = "api-base-url",
+        value_name = "URL",
+        value_parser = api::parse_base_url,
+        env = "BS_API_BASE_URL"
+    )]
+    api_base_url: Option<String>,
+
+    /// Match `--album` and `--artist` case sensitively.
+    #[arg(long = "case-sensitive", env = "BS_CASE_SENSITIVE")]
+    case_sensitive: bool,
+
+    #[arg(
+        short,
+        long,
+        value_name = "COOKIES_FILE",
+        value_delimiter = ',',
+        env = "BS_COOKIES"
+    )]
+    cookies: Vec<String>,
+
+    /// Only use cookies set for this domain (and its subdomains), e.g. for a
+    /// self-hosted instance.
+    #[arg(
+        long = "cookie-domain",
+        value_name = "DOMAIN",
+        default_value = cookies::DEFAULT_DOMAIN,
+        env = "BS_COOKIE_DOMAIN"
+    )]
+    cookie_domain: String,
+
+    /// Enables some extra debug output in certain scenarios.
+    #[arg(long, env = "BS_DEBUG")]
+    debug: bool,
+
+    /// Maximum number of releases to check.
+    #[arg(short = 'n', long, env = "BS_LIMIT")]
+    limit: Option<usize>,
+
+    /// Name of the user to check the releases of (must be logged in through
+    /// cookies).
+    #[clap(env = "BS_USER")]
+    user: String,
+}
+
+pub fn command(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let cookies_files = args
+        .cookies
+        .iter()
+        .map(|p| shellexpand::tilde(p).into_owned())
+        .collect::<Vec<_>>();
+    let cookies = cookies::get_domain_cookies(&cookies_files, &args.cookie_domain)?;
+    let api = api::Api::with_options(
+        cookies,
+        api::ClientOptions {
+            base_url: args.api_base_url.clone(),
+            ..Default::default()
+        },
+    );
+    let album = args
+        .album
+        .as_deref()
+        .map(|p| util::build_filter(p, args.case_sensitive))
+        .transpose()?;
+    let artist = args
+        .artist
+        .as_deref()
+        .map(|p| util::build_filter(p, args.case_sensitive))
+        .transpose()?;
+
+    let mut items = api
+        .get_download_urls(&args.user, artist.as_ref(), album.as_ref(), None)?
+        .download_urls
+        .into_iter()
+        .collect::<Vec<_>>();
+    items.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    for (id, info) in items.into_iter().take(args.limit.unwrap_or(usize::MAX)) {
+        match api.list_available_formats(&info.url, &args.debug) {
+            Ok(formats) => println!("{id}: {}", formats.join(", ")),
+            Err(e) => warn!("Failed to get formats for {id}: {e}"),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,132 @@
+use crate::error::BandsnatchError;
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Environment variables set by `Config::apply`, so that they can be replaced
+/// when the config is reloaded.
+static APPLIED_ENV: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Default option values loaded from a TOML file, e.g.
+///
+/// ```toml
+/// format = "flac"
+/// jobs = 8
+/// output-folder = "~/Music"
+/// user = "ovyerus"
+/// ```
+///
+/// Keys are the long names of any option. Values are applied through each
+/// option's `BS_*` environment variable, so the order of precedence ends up
+/// being: CLI flag > environment variable > config file > built-in default.
+pub struct Config {
+    values: toml::Table,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/bandsnatch/config.toml`, falling back to
+    /// `~/.config/bandsnatch/config.toml`.
+    pub fn default_path() -> PathBuf {
+        let config_home = env::var("XDG_CONFIG_HOME")
+            .ok()
+            .filter(|p| !p.is_empty())
+            .unwrap_or_else(|| shellexpand::tilde("~/.config").into_owned());
+
+        Path::new(&config_home)
+            .join("bandsnatch")
+            .join("config.toml")
+    }
+
+    /// Load the config file at `path`, or the default location if not given.
+    /// A missing file at the default location is not an error.
+    pub fn load(path: Option<&str>) -> Result<Self, BandsnatchError> {
+        let path = match path {
+            Some(p) => PathBuf::from(shellexpand::tilde(p).as_ref()),
+            None => {
+                let p = Self::default_path();
+                if !p.exists() {
+                    return Ok(Self {
+                        values: toml::Table::new(),
+                    });
+                }
+                p
+            }
+        };
+
+        debug!("Loading config from {}", path.display());
+        let content = fs::read_to_string(&path).map_err(|e| {
+            let msg = format!("failed to read config file {}: {e}", path.display());
+            BandsnatchError::Io(io::Error::new(e.kind(), msg))
+        })?;
+        let values = content.parse::<toml::Table>().map_err(|e| {
+            BandsnatchError::Parse(format!(
+                "failed to parse config file {}: {e}",
+                path.display()
+            ))
+        })?;
+
+        Ok(Self { values })
+    }
+
+    /// Expose config values to `cmd` by setting the environment variable of
+    /// each matching option, unless it's already set. Variables set by a
+    /// previous call are replaced, so that the config can be reloaded. Must be
+    /// called while no other threads are reading the environment.
+    pub fn apply(&self, cmd: &clap::Command) {
+        let mut applied = APPLIED_ENV.lock().unwrap();
+        for env_name in applied.drain(..) {
+            env::remove_var(env_name);
+        }
+
+        for (key, value) in &self.values {
+            let Some(arg) = find_arg(cmd, key) else {
+                warn!("Unknown option `{key}` in config file, ignoring");
+                continue;
+            };
+            let env_name = arg
+                .get_env()
+                .expect("only options with an env var are found")
+                .to_string_lossy()
+                .into_owned();
+
+            if env::var_os(&env_name).is_some() {
+                continue;
+            }
+
+            let value = match value {
+                toml::Value::String(s) => s.clone(),
+                // The environment variable can only hold a list if the option
+                // splits its value up.
+                toml::Value::Array(arr) if arr.len() > 1 && arg.get_value_delimiter().is_none() => {
+                    warn!("Option `{key}` in config file can't be a list, ignoring");
+                    continue;
+                }
+                toml::Value::Array(arr) => arr
+                    .iter()
+                    .map(|v| v.as_str().map(String::from).unwrap_or(v.to_string()))
+                    .collect::<Vec<_>>()
+                    .join(","),
+                toml::Value::Table(_) => {
+                    warn!("Option `{key}` in config file can't be a table, ignoring");
+                    continue;
+                }
+                v => v.to_string(),
+            };
+
+            trace!("Setting {env_name} from config file");
+            env::set_var(&env_name, value);
+            applied.push(env_name);
+        }
+    }
+}
+
+/// Find the option with the long name (or id) `key` and an environment
+/// variable in `cmd` or any of its subcommands.
+fn find_arg<'a>(cmd: &'a clap::Command, key: &str) -> Option<&'a clap::Arg> {
+    cmd.get_arguments()
+        .filter(|a| a.get_long() == Some(key) || a.get_id() == key)
+        .find(|a| a.get_env().is_some())
+        .or_else(|| cmd.get_subcommands().find_map(|c| find_arg(c, key)))
+}
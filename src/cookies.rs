@@ -1,8 +1,21 @@
 // use cookie_store::{Cookie, CookieStore};
+mod browser;
+pub use browser::{Browser, BrowserCookieStore};
+
+use crate::error::BandsnatchError;
+use chrono::{DateTime, Duration, Utc};
 use reqwest::cookie::Jar;
 use serde::Deserialize;
-use std::fs;
+use serde_aux::prelude::deserialize_option_number_from_string;
 use std::vec::Vec;
+use std::{env, fmt, fs};
+
+/// Environment variable that cookies are read from when no cookies file is
+/// given.
+const COOKIES_STRING_ENV: &str = "BS_COOKIES_STRING";
+
+/// Domain that Bandcamp's cookies are set on.
+pub const DEFAULT_DOMAIN: &str = "bandcamp.com";
 
 #[derive(Deserialize, Debug)]
 pub struct RawCookie {
@@ -12,11 +25,90 @@ pub struct RawCookie {
     name: String,
     #[serde(rename = "Content raw")]
     content: String,
+    /// Unix timestamp of when the cookie expires, if it isn't a session cookie.
+    #[serde(
+        rename = "Expires raw",
+        default,
+        deserialize_with = "deserialize_option_number_from_string"
+    )]
+    expires: Option<i64>,
+}
+
+/// Name of the cookie that Bandcamp keeps you logged in with.
+const LOGIN_COOKIE: &str = "identity";
+
+/// When the login cookie in `cookies` expires, if that's known.
+pub fn login_expiry(cookies: &[RawCookie]) -> Option<DateTime<Utc>> {
+    cookies
+        .iter()
+        .find(|c| c.name == LOGIN_COOKIE)
+        .and_then(|c| c.expires)
+        .and_then(|expires| DateTime::from_timestamp(expires, 0))
+}
+
+/// How soon a cookie has to expire to be warned about.
+const EXPIRY_WARNING_DAYS: i64 = 7;
+
+/// What's known about a cookie, for saying when it expires.
+#[derive(Debug)]
+pub struct CookieInfo {
+    pub name: String,
+    /// When the cookie expires, or `None` for session cookies.
+    pub expires: Option<DateTime<Utc>>,
+    pub domain: String,
+}
+
+/// A cookie that's no good anymore, or won't be soon.
+#[derive(Debug)]
+pub enum CookieWarning {
+    Expired(CookieInfo),
+    ExpiringSoon(CookieInfo),
+}
+
+impl fmt::Display for CookieWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (info, verb) = match self {
+            Self::Expired(info) => (info, "expired"),
+            Self::ExpiringSoon(info) => (info, "expires"),
+        };
+        write!(f, "Cookie `{}` for {} {verb}", info.name, info.domain)?;
+        if let Some(expires) = info.expires {
+            write!(f, " on {}", expires.format("%Y-%m-%d %H:%M UTC"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Find cookies that have already expired, or will within
+/// `EXPIRY_WARNING_DAYS`. Expired cookies tend to cause confusing errors
+/// partway through a run, so it's better to say so up front.
+pub fn check_expiry(cookies: &[RawCookie]) -> Vec<CookieWarning> {
+    let now = Utc::now();
+    let soon = now + Duration::days(EXPIRY_WARNING_DAYS);
+
+    cookies
+        .iter()
+        .filter_map(|c| {
+            let expires = DateTime::from_timestamp(c.expires?, 0)?;
+            let info = CookieInfo {
+                name: c.name.clone(),
+                expires: Some(expires),
+                domain: c.host.trim_start_matches("https://").to_string(),
+            };
+            if expires <= now {
+                Some(CookieWarning::Expired(info))
+            } else if expires <= soon {
+                Some(CookieWarning::ExpiringSoon(info))
+            } else {
+                None
+            }
+        })
+        .collect()
 }
 
 /// Get hashmap of cookies from a `cookies.json` string.
-fn get_json_cookies(json: &str) -> Vec<RawCookie> {
-    let raw = serde_json::from_str::<Vec<RawCookie>>(json).unwrap();
+fn get_json_cookies(json: &str) -> Result<Vec<RawCookie>, BandsnatchError> {
+    let raw = serde_json::from_str::<Vec<RawCookie>>(json)?;
     let mut vec = Vec::<RawCookie>::new();
     let cookie_iter = raw.iter();
 
@@ -26,28 +118,36 @@ fn get_json_cookies(json: &str) -> Vec<RawCookie> {
             host: c.host.clone(),
             name: c.name.clone(),
             content: c.content.clone(),
+            // Session cookies are exported as expiring at 0.
+            expires: c.expires.filter(|e| *e > 0),
         })
     }
 
-    vec
+    Ok(vec)
 }
 
-fn get_text_cookies(content: &str) -> Vec<RawCookie> {
-    let lines = content.split('\n');
+/// Get cookies from a Netscape format `cookies.txt`, as exported by
+/// extensions like "Get cookies.txt".
+fn get_netscape_cookies(content: &str) -> Vec<RawCookie> {
     let mut vec = Vec::<RawCookie>::new();
 
-    for l in lines {
+    for l in content.lines() {
+        // HttpOnly cookies are written with this prefix, which would otherwise
+        // make them look like comments.
+        let l = l.strip_prefix("#HttpOnly_").unwrap_or(l);
         if !l.starts_with('#') {
             let columns: Vec<&str> = l.split('\t').collect();
             if columns.len() == 7 {
                 // Fix problem where cookies.txt only gives us raw domains.
                 let mut host = "https://".to_owned();
-                host.push_str(columns[0]);
+                host.push_str(columns[0].trim_start_matches('.'));
 
                 vec.push(RawCookie {
-                    host: host,
+                    host,
                     name: String::from(columns[5]),
                     content: String::from(columns[6]),
+                    // Session cookies have an expiry of 0.
+                    expires: columns[4].parse().ok().filter(|e| *e > 0),
                 })
             }
         }
@@ -56,27 +156,126 @@ fn get_text_cookies(content: &str) -> Vec<RawCookie> {
     vec
 }
 
-// get cookies from firefox?
+/// Whether `content` looks like a Netscape format cookies file, going by its
+/// header.
+fn is_netscape_cookies(content: &str) -> bool {
+    content.lines().next().is_some_and(|l| {
+        l.starts_with("# Netscape HTTP Cookie File") || l.starts_with("# HTTP Cookie File")
+    })
+}
 
-pub fn get_bandcamp_cookies(path: Option<&str>) -> Result<Vec<RawCookie>, String> {
-    if let Some(path) = path {
-        let data = fs::read_to_string(path)
-            .unwrap_or_else(|_| panic!("Cannot read cookies file '{path}'"));
-        // TODO: need to return results from these functions
-        let cookies = if path.ends_with(".json") {
-            get_json_cookies(&data)
-        } else {
-            get_text_cookies(&data)
-        };
+/// Whether a cookie for `host` (like `https://bandcamp.com`) would be sent to
+/// `domain` or its subdomains.
+fn is_for_domain(host: &str, domain: &str) -> bool {
+    let host = host
+        .trim_start_matches("https://")
+        .trim_start_matches("http://");
+    host == domain || host.ends_with(&format!(".{domain}"))
+}
+
+/// Get cookies from a `Cookie` header style string, like `a=1; b=2`, for
+/// `domain`.
+fn get_header_cookies(header: &str, domain: &str) -> Vec<RawCookie> {
+    header
+        .split(';')
+        .filter_map(|pair| pair.trim().split_once('='))
+        .map(|(name, content)| RawCookie {
+            host: format!("https://{domain}"),
+            name: name.trim().to_string(),
+            content: content.trim().to_string(),
+            expires: None,
+        })
+        .collect()
+}
 
-        return Ok(cookies);
+/// Get cookies for `domain` from the environment variable `var`, in the
+/// format of a `Cookie` header.
+pub fn get_env_cookies(var: &str, domain: &str) -> Result<Vec<RawCookie>, BandsnatchError> {
+    let header = env::var(var)
+        .map_err(|_| BandsnatchError::Cookie(format!("Environment variable `{var}` is not set")))?;
+    let cookies = get_header_cookies(&header, domain);
+    if cookies.is_empty() {
+        return Err(BandsnatchError::Cookie(format!(
+            "No cookies found in `{var}`"
+        )));
     }
 
-    // If no path provided, look for local cookies
+    Ok(cookies)
+}
 
-    get_bandcamp_cookies(Some("./cookies.json"))
-        .or_else(|_| get_bandcamp_cookies(Some("./cookies.txt")))
-        .or(Err(String::from("Failed to get cookies")))
+pub fn get_bandcamp_cookies(paths: &[String]) -> Result<Vec<RawCookie>, BandsnatchError> {
+    get_domain_cookies(paths, DEFAULT_DOMAIN)
+}
+
+/// Get cookies for `domain` (and its subdomains) from the files at `paths`, or
+/// from `BS_COOKIES_STRING` or a cookies file in the current folder if no
+/// paths are given. Cookies in later files replace ones with the same name
+/// and host from earlier files.
+pub fn get_domain_cookies(
+    paths: &[String],
+    domain: &str,
+) -> Result<Vec<RawCookie>, BandsnatchError> {
+    if !paths.is_empty() {
+        let mut merged = Vec::<RawCookie>::new();
+        for path in paths {
+            for cookie in read_cookies_file(path, domain)? {
+                merged.retain(|c| c.host != cookie.host || c.name != cookie.name);
+                merged.push(cookie);
+            }
+        }
+
+        return Ok(merged);
+    }
+
+    // If no path provided, look for cookies in the environment, and then
+    // local cookies
+    if env::var_os(COOKIES_STRING_ENV).is_some() {
+        return get_env_cookies(COOKIES_STRING_ENV, domain);
+    }
+
+    read_cookies_file("./cookies.json", domain)
+        .or_else(|_| read_cookies_file("./cookies.txt", domain))
+        .or(Err(BandsnatchError::Cookie(String::from(
+            "Failed to get cookies",
+        ))))
+}
+
+/// Get cookies for `domain` (and its subdomains) from a Netscape or JSON
+/// format cookies file.
+fn read_cookies_file(path: &str, domain: &str) -> Result<Vec<RawCookie>, BandsnatchError> {
+    let data = fs::read_to_string(path)
+        .map_err(|e| BandsnatchError::Cookie(format!("Cannot read cookies file '{path}': {e}")))?;
+    let cookies = if is_netscape_cookies(&data) {
+        get_netscape_cookies(&data)
+    } else if data.trim_start().starts_with('[') || path.ends_with(".json") {
+        get_json_cookies(&data).map_err(|e| {
+            BandsnatchError::Parse(format!("Cannot read cookies file '{path}': {e}"))
+        })?
+    } else {
+        get_netscape_cookies(&data)
+    };
+
+    Ok(cookies
+        .into_iter()
+        .filter(|c| is_for_domain(&c.host, domain))
+        .collect())
+}
+
+/// Get cookies from whichever source was picked, preferring a browser, then
+/// an environment variable, then a file. Only cookies for `domain` are kept.
+pub fn load(
+    browser: Option<Browser>,
+    env_var: Option<&str>,
+    paths: &[String],
+    domain: &str,
+) -> Result<Vec<RawCookie>, BandsnatchError> {
+    match (browser, env_var) {
+        (Some(browser), _) => BrowserCookieStore::new(browser, domain)
+            .cookies()
+            .map_err(BandsnatchError::Cookie),
+        (None, Some(var)) => get_env_cookies(var, domain),
+        (None, None) => get_domain_cookies(paths, domain),
+    }
 }
 
 pub fn fill_cookie_jar(cookies: Vec<RawCookie>) -> Jar {
@@ -86,6 +285,7 @@ pub fn fill_cookie_jar(cookies: Vec<RawCookie>) -> Jar {
         host,
         name,
         content,
+        ..
     } in cookies
     {
         let host = url::Url::parse(&host).expect("failed to unwrap cookies");
@@ -95,3 +295,102 @@ pub fn fill_cookie_jar(cookies: Vec<RawCookie>) -> Jar {
 
     jar
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util;
+
+    const NETSCAPE: &str = "# Netscape HTTP Cookie File
+# https://curl.haxx.se/rfc/cookie_spec.html
+# This is a generated file! Do not edit.
+
+.bandcamp.com\tTRUE\t/\tTRUE\t1893456000\tclient_id\tabc123
+#HttpOnly_.bandcamp.com\tTRUE\t/\tTRUE\t1893456000\tidentity\tsecret
+bandcamp.com\tFALSE\t/\tFALSE\t0\tsession\txyz
+.example.com\tTRUE\t/\tFALSE\t1893456000\tother\tnope
+";
+
+    #[test]
+    fn detects_netscape_cookies() {
+        assert!(is_netscape_cookies(NETSCAPE));
+        assert!(is_netscape_cookies("# HTTP Cookie File\n"));
+        assert!(!is_netscape_cookies("[{\"name\": \"a\"}]"));
+        assert!(!is_netscape_cookies(
+            "bandcamp.com\tTRUE\t/\tTRUE\t0\ta\tb\n"
+        ));
+    }
+
+    #[test]
+    fn parses_netscape_cookies() {
+        let cookies = get_netscape_cookies(NETSCAPE);
+        let summary = cookies
+            .iter()
+            .map(|c| (c.host.as_str(), c.name.as_str(), c.content.as_str()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            summary,
+            [
+                ("https://bandcamp.com", "client_id", "abc123"),
+                ("https://bandcamp.com", "identity", "secret"),
+                ("https://bandcamp.com", "session", "xyz"),
+                ("https://example.com", "other", "nope"),
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_cookies_for_domain() {
+        assert!(is_for_domain("https://bandcamp.com", "bandcamp.com"));
+        assert!(is_for_domain("https://.bandcamp.com", "bandcamp.com"));
+        assert!(is_for_domain("https://artist.bandcamp.com", "bandcamp.com"));
+        assert!(!is_for_domain("https://notbandcamp.com", "bandcamp.com"));
+    }
+
+    #[test]
+    fn session_cookies_do_not_expire() {
+        let json = r#"[
+            {"Host raw": "https://.bandcamp.com/", "Name raw": "identity", "Content raw": "secret", "Expires raw": "1893456000"},
+            {"Host raw": "https://.bandcamp.com/", "Name raw": "session", "Content raw": "xyz", "Expires raw": "0"},
+            {"Host raw": "https://.bandcamp.com/", "Name raw": "other", "Content raw": "abc", "Expires raw": 0}
+        ]"#;
+        let expiries = |cookies: Vec<RawCookie>| {
+            cookies
+                .into_iter()
+                .map(|c| (c.name, c.expires))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            expiries(get_json_cookies(json).unwrap()),
+            [
+                (String::from("identity"), Some(1893456000)),
+                (String::from("session"), None),
+                (String::from("other"), None),
+            ]
+        );
+        assert_eq!(
+            expiries(get_netscape_cookies(NETSCAPE)),
+            [
+                (String::from("client_id"), Some(1893456000)),
+                (String::from("identity"), Some(1893456000)),
+                (String::from("session"), None),
+                (String::from("other"), Some(1893456000)),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_netscape_cookies_file_for_domain() {
+        let dir = util::test_dir("netscape-cookies");
+        // The extension shouldn't matter, only what's in the file.
+        let path = dir.join("cookies.json");
+        fs::write(&path, NETSCAPE).unwrap();
+
+        let cookies = read_cookies_file(path.to_str().unwrap(), DEFAULT_DOMAIN).unwrap();
+        let names = cookies.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+
+        assert_eq!(names, ["client_id", "identity", "session"]);
+    }
+}
@@ -0,0 +1,285 @@
+use super::{is_for_domain, RawCookie};
+use clap::ValueEnum;
+use rusqlite::{Connection, OpenFlags};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// Browsers that cookies can be read from directly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Browser {
+    Firefox,
+    Chrome,
+    Chromium,
+}
+
+/// Reads Bandcamp cookies straight out of a browser's cookie database, from
+/// the most recently used profile.
+pub struct BrowserCookieStore {
+    browser: Browser,
+    /// Only cookies for this domain and its subdomains are read.
+    domain: String,
+}
+
+fn home() -> PathBuf {
+    PathBuf::from(shellexpand::tilde("~").as_ref())
+}
+
+/// Folders which may contain profiles with a `cookies.sqlite`.
+fn firefox_roots() -> Vec<PathBuf> {
+    let mut roots = vec![
+        home().join(".mozilla/firefox"),
+        home().join("snap/firefox/common/.mozilla/firefox"),
+        home().join(".var/app/org.mozilla.firefox/.mozilla/firefox"),
+        home().join("Library/Application Support/Firefox/Profiles"),
+    ];
+    if let Ok(appdata) = env::var("APPDATA") {
+        roots.push(Path::new(&appdata).join("Mozilla/Firefox/Profiles"));
+    }
+    roots
+}
+
+/// Folders which may contain profiles with a `Cookies` database.
+fn chromium_roots(browser: Browser) -> Vec<PathBuf> {
+    let (linux, macos, windows) = match browser {
+        Browser::Chrome => ("google-chrome", "Google/Chrome", "Google/Chrome/User Data"),
+        Browser::Chromium => ("chromium", "Chromium", "Chromium/User Data"),
+        Browser::Firefox => unreachable!(),
+    };
+
+    let mut roots = vec![
+        home().join(".config").join(linux),
+        home().join("Library/Application Support").join(macos),
+    ];
+    if let Ok(local_appdata) = env::var("LOCALAPPDATA") {
+        roots.push(Path::new(&local_appdata).join(windows));
+    }
+    roots
+}
+
+/// Find the most recently modified `filename` directly inside of any profile
+/// folder in `roots`, or the `Network` folder inside of it.
+fn find_newest(roots: &[PathBuf], filename: &str) -> Option<PathBuf> {
+    roots
+        .iter()
+        .filter_map(|root| fs::read_dir(root).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .flat_map(|entry| {
+            let profile = entry.path();
+            [
+                profile.join(filename),
+                profile.join("Network").join(filename),
+            ]
+        })
+        .filter_map(|path| {
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+            Some((modified, path))
+        })
+        .max_by_key(|(modified, _)| *modified)
+        .map(|(_, path)| path)
+}
+
+/// Open a copy of the database at `path`, as browsers keep it locked while
+/// they're running.
+fn open_copy(path: &Path) -> Result<(Connection, PathBuf), String> {
+    let copy = env::temp_dir().join(format!("bandsnatch-cookies-{}.sqlite", std::process::id()));
+    fs::copy(path, &copy)
+        .map_err(|e| format!("Failed to copy cookies from {}: {e}", path.display()))?;
+    let conn = Connection::open_with_flags(&copy, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("Failed to open cookies from {}: {e}", path.display()))?;
+    Ok((conn, copy))
+}
+
+/// Turn a cookie's domain into something `fill_cookie_jar` can use.
+fn host_url(domain: &str) -> String {
+    format!("https://{}", domain.trim_start_matches('.'))
+}
+
+impl BrowserCookieStore {
+    pub fn new(browser: Browser, domain: &str) -> Self {
+        Self {
+            browser,
+            domain: domain.to_string(),
+        }
+    }
+
+    /// Pattern matching hosts of cookies for `domain`, for `LIKE`. Also
+    /// matches other domains ending in it, so rows still have to be checked
+    /// with `is_for_domain`.
+    fn host_pattern(&self) -> String {
+        format!("%{}", self.domain)
+    }
+
+    pub fn cookies(&self) -> Result<Vec<RawCookie>, String> {
+        let (path, mut cookies) = match self.browser {
+            Browser::Firefox => {
+                let path = find_newest(&firefox_roots(), "cookies.sqlite")
+                    .ok_or("Couldn't find a Firefox profile with cookies")?;
+                let cookies = self.firefox_cookies(&path)?;
+                (path, cookies)
+            }
+            Browser::Chrome | Browser::Chromium => {
+                let path = find_newest(&chromium_roots(self.browser), "Cookies").ok_or(format!(
+                    "Couldn't find a {:?} profile with cookies",
+                    self.browser
+                ))?;
+                let cookies = self.chromium_cookies(&path)?;
+                (path, cookies)
+            }
+        };
+        cookies.retain(|c| is_for_domain(&c.host, &self.domain));
+
+        debug!(
+            "Read {} Bandcamp cookies from {}",
+            cookies.len(),
+            path.display()
+        );
+        if cookies.is_empty() {
+            return Err(format!(
+                "No Bandcamp cookies in {}, make sure you're logged in",
+                path.display()
+            ));
+        }
+
+        Ok(cookies)
+    }
+
+    fn firefox_cookies(&self, path: &Path) -> Result<Vec<RawCookie>, String> {
+        let (conn, copy) = open_copy(path)?;
+        let result = (|| {
+            let mut stmt = conn
+                .prepare("SELECT host, name, value, expiry FROM moz_cookies WHERE host LIKE ?1")?;
+            let rows = stmt.query_map([self.host_pattern()], |row| {
+                Ok(RawCookie {
+                    host: host_url(&row.get::<_, String>(0)?),
+                    name: row.get(1)?,
+                    content: row.get(2)?,
+                    expires: row.get::<_, Option<i64>>(3)?.filter(|e| *e > 0),
+                })
+            })?;
+            rows.collect::<Result<Vec<_>, _>>()
+        })()
+        .map_err(|e| format!("Failed to read Firefox cookies: {e}"));
+
+        drop(conn);
+        let _ = fs::remove_file(copy);
+        result
+    }
+
+    fn chromium_cookies(&self, path: &Path) -> Result<Vec<RawCookie>, String> {
+        let (conn, copy) = open_copy(path)?;
+        let result = (|| {
+            // Newer versions prefix encrypted values with a hash of the domain.
+            let version = conn
+                .query_row("SELECT value FROM meta WHERE key = 'version'", [], |row| {
+                    row.get::<_, String>(0)
+                })
+                .ok()
+                .and_then(|v| v.parse::<u32>().ok())
+                .unwrap_or(0);
+
+            let mut stmt = conn.prepare(
+                "SELECT host_key, name, value, encrypted_value, expires_utc FROM cookies WHERE host_key LIKE ?1",
+            )?;
+            let rows = stmt.query_map([self.host_pattern()], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Vec<u8>>(3)?,
+                    row.get::<_, Option<i64>>(4)?,
+                ))
+            })?;
+            Ok::<_, rusqlite::Error>((version, rows.collect::<Result<Vec<_>, _>>()?))
+        })()
+        .map_err(|e| format!("Failed to read {:?} cookies: {e}", self.browser));
+
+        drop(conn);
+        let _ = fs::remove_file(copy);
+        let (version, rows) = result?;
+
+        rows.into_iter()
+            .map(|(host, name, value, encrypted, expires_utc)| {
+                let content = if !value.is_empty() || encrypted.is_empty() {
+                    value
+                } else {
+                    let mut decrypted = decrypt_chromium_value(self.browser, &encrypted)?;
+                    if version >= 24 && decrypted.len() >= 32 {
+                        decrypted.drain(..32);
+                    }
+                    String::from_utf8(decrypted)
+                        .map_err(|_| format!("Cookie `{name}` didn't decrypt to valid text"))?
+                };
+
+                Ok(RawCookie {
+                    host: host_url(&host),
+                    name,
+                    content,
+                    expires: expires_utc.and_then(chromium_to_unix),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Chromium stores times as microseconds since 1601, with 0 meaning a session
+/// cookie.
+fn chromium_to_unix(time: i64) -> Option<i64> {
+    const UNIX_EPOCH_OFFSET_SECS: i64 = 11_644_473_600;
+    (time > 0).then(|| time / 1_000_000 - UNIX_EPOCH_OFFSET_SECS)
+}
+
+/// Decrypt a `v10` encrypted cookie value, which is AES-128-CBC with a key
+/// derived from a password that depends on the platform.
+fn decrypt_chromium_value(browser: Browser, encrypted: &[u8]) -> Result<Vec<u8>, String> {
+    use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+
+    let Some(data) = encrypted.strip_prefix(b"v10") else {
+        return Err(String::from(
+            "Cookies are encrypted with the system keyring, which isn't supported yet. Try exporting them to a file instead",
+        ));
+    };
+
+    let (password, iterations) = chromium_password(browser)?;
+    let mut key = [0u8; 16];
+    pbkdf2::pbkdf2_hmac::<sha1::Sha1>(password.as_bytes(), b"saltysalt", iterations, &mut key);
+
+    cbc::Decryptor::<aes::Aes128>::new(&key.into(), &[b' '; 16].into())
+        .decrypt_padded_vec_mut::<Pkcs7>(data)
+        .map_err(|_| String::from("Failed to decrypt cookie, the key might be wrong"))
+}
+
+/// The password `v10` values are encrypted with, and how many PBKDF2
+/// iterations to derive the key with.
+#[cfg(target_os = "macos")]
+fn chromium_password(browser: Browser) -> Result<(String, u32), String> {
+    let service = match browser {
+        Browser::Chromium => "Chromium Safe Storage",
+        _ => "Chrome Safe Storage",
+    };
+    let output = std::process::Command::new("security")
+        .args(["find-generic-password", "-w", "-s", service])
+        .output()
+        .map_err(|e| format!("Failed to get {service} from the keychain: {e}"))?;
+    if !output.status.success() {
+        return Err(format!("Failed to get {service} from the keychain"));
+    }
+
+    let password = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok((password, 1003))
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn chromium_password(_browser: Browser) -> Result<(String, u32), String> {
+    // Used whenever there's no keyring available.
+    Ok((String::from("peanuts"), 1))
+}
+
+#[cfg(not(unix))]
+fn chromium_password(_browser: Browser) -> Result<(String, u32), String> {
+    Err(String::from(
+        "Decrypting Chrome cookies isn't supported on this platform yet. Try exporting them to a file instead",
+    ))
+}
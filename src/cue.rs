@@ -0,0 +1,72 @@
+use crate::{api::structs::DigitalItem, error::BandsnatchError, util};
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// CUE file type for an audio file, going by its extension.
+fn file_type(path: &Path) -> &'static str {
+    match path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase)
+        .as_deref()
+    {
+        Some("mp3") => "MP3",
+        Some("aif" | "aiff") => "AIFF",
+        _ => "WAVE",
+    }
+}
+
+/// Quotes aren't escapable in CUE sheets.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "'"))
+}
+
+/// Write a `<album>.cue` sheet for the audio files in `path`, if there's more
+/// than one of them. Track titles come from `item` when it lists the same
+/// number of tracks, and the file names otherwise.
+pub fn generate(path: &Path, item: &DigitalItem) -> Result<Option<PathBuf>, BandsnatchError> {
+    let mut files = vec![];
+    util::find_audio_files(path, &mut files)?;
+    if files.len() < 2 {
+        return Ok(None);
+    }
+    // Bandcamp numbers its file names, so this puts them in track order.
+    files.sort();
+
+    let mut tracks = item.tracks.clone();
+    tracks.sort_by_key(|t| t.track_num);
+    let use_item_tracks = tracks.len() == files.len();
+
+    let mut cue = String::new();
+    writeln!(cue, "PERFORMER {}", quote(&item.artist))?;
+    writeln!(cue, "TITLE {}", quote(&item.title))?;
+
+    for (i, file) in files.iter().enumerate() {
+        let relative = file.strip_prefix(path).unwrap_or(file);
+        let title = if use_item_tracks {
+            tracks[i].title.clone()
+        } else {
+            file.file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+
+        writeln!(
+            cue,
+            "FILE {} {}",
+            quote(&relative.to_string_lossy()),
+            file_type(file)
+        )?;
+        writeln!(cue, "  TRACK {:02} AUDIO", i + 1)?;
+        writeln!(cue, "    TITLE {}", quote(&title))?;
+        writeln!(cue, "    PERFORMER {}", quote(&item.artist))?;
+        writeln!(cue, "    INDEX 01 00:00:00")?;
+    }
+
+    let cue_path = path.join(format!("{}.cue", util::make_string_fs_safe(&item.title)));
+    fs::write(&cue_path, cue)?;
+    Ok(Some(cue_path))
+}
@@ -0,0 +1,122 @@
+use reqwest::StatusCode;
+use std::{fmt, io, time::Duration};
+use thiserror::Error;
+
+/// Errors from talking to Bandcamp and keeping track of what's been
+/// downloaded, split up enough to tell which ones are worth retrying.
+#[derive(Error)]
+pub enum BandsnatchError {
+    /// Bandcamp sent back something other than what we asked for.
+    #[error("{0}")]
+    Api(String),
+    /// Bandcamp answered a request with an error status.
+    #[error("request failed with status {status} for url {url}")]
+    Status {
+        status: StatusCode,
+        url: reqwest::Url,
+    },
+    /// A request couldn't be sent, or its response couldn't be read.
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    /// The cache database couldn't be read or written.
+    #[error("cache error: {0}")]
+    Cache(#[from] rusqlite::Error),
+    /// Cookies couldn't be found or read.
+    #[error("{0}")]
+    Cookie(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// Data from Bandcamp wasn't in the shape we expected.
+    #[error("{0}")]
+    Parse(String),
+    /// The cookies aren't logged in to the account being looked at.
+    #[error("{0}")]
+    Auth(String),
+    /// Bandcamp kept ratelimiting us, last asking to wait this long.
+    #[error("still ratelimited by Bandcamp, try again in {}s", .0.as_secs())]
+    RateLimit(Duration),
+    /// A download turned out to be something else, usually an error page from
+    /// an expired link.
+    #[error("{0}")]
+    UnexpectedContent(String),
+    /// A run couldn't go ahead, like from options that don't work together or
+    /// running out of space.
+    #[error("{0}")]
+    Run(String),
+}
+
+impl BandsnatchError {
+    /// Whether trying again later might work, rather than failing the same way
+    /// every time.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Api(_) | Self::Io(_) | Self::RateLimit(_) | Self::UnexpectedContent(_) => true,
+            Self::Status { status, .. } => {
+                status.is_server_error() || *status == StatusCode::REQUEST_TIMEOUT
+            }
+            Self::Http(e) => !e.is_decode() && !e.is_builder(),
+            Self::Cache(_) | Self::Cookie(_) | Self::Parse(_) | Self::Auth(_) | Self::Run(_) => {
+                false
+            }
+        }
+    }
+}
+
+// `main` prints errors it gets with `Debug`, so keep them readable.
+impl fmt::Debug for BandsnatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl From<serde_json::Error> for BandsnatchError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Parse(e.to_string())
+    }
+}
+
+impl From<std::str::Utf8Error> for BandsnatchError {
+    fn from(e: std::str::Utf8Error) -> Self {
+        Self::Parse(e.to_string())
+    }
+}
+
+// Only comes from writing to strings, which can't actually fail.
+impl From<fmt::Error> for BandsnatchError {
+    fn from(e: fmt::Error) -> Self {
+        Self::Io(io::Error::other(e))
+    }
+}
+
+impl From<regex::Error> for BandsnatchError {
+    fn from(e: regex::Error) -> Self {
+        Self::Parse(e.to_string())
+    }
+}
+
+impl From<csv::Error> for BandsnatchError {
+    fn from(e: csv::Error) -> Self {
+        Self::Io(e.into())
+    }
+}
+
+impl From<notify::Error> for BandsnatchError {
+    fn from(e: notify::Error) -> Self {
+        Self::Io(io::Error::other(e))
+    }
+}
+
+// Lets `bail!` be used in commands.
+impl From<simple_error::SimpleError> for BandsnatchError {
+    fn from(e: simple_error::SimpleError) -> Self {
+        Self::Run(e.to_string())
+    }
+}
+
+// Broken archives are usually from a download going wrong, so they're worth
+// trying again.
+impl From<zip::result::ZipError> for BandsnatchError {
+    fn from(e: zip::result::ZipError) -> Self {
+        Self::Api(format!("invalid zip file: {e}"))
+    }
+}
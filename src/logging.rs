@@ -0,0 +1,176 @@
+use clap::ColorChoice;
+use env_logger::{fmt::WriteStyle, Env, DEFAULT_FILTER_ENV};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde_json::json;
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, OnceLock, RwLock,
+    },
+};
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// File that every log event from Bandsnatch gets written to as a JSON line,
+/// regardless of what is shown on stderr.
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    /// Size at which the file gets moved to `<path>.1` and started again.
+    rotate_bytes: Option<u64>,
+}
+
+impl LogFile {
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        if let Some(max) = self.rotate_bytes {
+            if self.file.metadata()?.len() + line.len() as u64 > max {
+                let mut rotated = self.path.as_os_str().to_owned();
+                rotated.push(".1");
+                fs::rename(&self.path, rotated)?;
+                self.file = open_append(&self.path)?;
+            }
+        }
+
+        self.file.write_all(line.as_bytes())
+    }
+}
+
+/// Logger which writes to stderr through `env_logger`, and optionally to a
+/// JSONL file as well.
+struct Logger {
+    inner: RwLock<env_logger::Logger>,
+    file: Mutex<Option<LogFile>>,
+    /// Only show warnings and errors on stderr.
+    quiet: AtomicBool,
+}
+
+impl Logger {
+    fn wants_file(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= LevelFilter::Debug
+            && metadata.target().starts_with(env!("CARGO_PKG_NAME"))
+            && self.file.lock().is_ok_and(|f| f.is_some())
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.read().unwrap().enabled(metadata) || self.wants_file(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        let hidden = self.quiet.load(Ordering::Relaxed) && record.level() > LevelFilter::Warn;
+        let inner = self.inner.read().unwrap();
+        if inner.matches(record) && !hidden {
+            inner.log(record);
+        }
+        drop(inner);
+
+        if !self.wants_file(record.metadata()) {
+            return;
+        }
+
+        let line = json!({
+            "level": record.level().as_str().to_lowercase(),
+            "msg": record.args().to_string(),
+            "ts": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        });
+
+        if let Ok(mut guard) = self.file.lock() {
+            if let Some(file) = guard.as_mut() {
+                // Nowhere sensible to report this.
+                let _ = file.write(&format!("{line}\n"));
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.read().unwrap().flush();
+        if let Ok(mut guard) = self.file.lock() {
+            if let Some(LogFile { file, .. }) = guard.as_mut() {
+                let _ = file.flush();
+            }
+        }
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    File::options().create(true).append(true).open(path)
+}
+
+fn build_inner(style: Option<WriteStyle>) -> env_logger::Logger {
+    // TODO: custom format
+    // TODO: make default based on what release target
+    let env = Env::default().filter_or(DEFAULT_FILTER_ENV, "bandsnatch=info");
+    let mut builder = env_logger::Builder::from_env(env);
+    if let Some(style) = style {
+        builder.write_style(style);
+    }
+    builder.build()
+}
+
+/// Set up logging to stderr, configured through `RUST_LOG`.
+pub fn init() {
+    let inner = build_inner(None);
+    let max_level = inner.filter();
+
+    let logger = LOGGER.get_or_init(|| Logger {
+        inner: RwLock::new(inner),
+        file: Mutex::new(None),
+        quiet: AtomicBool::new(false),
+    });
+    log::set_logger(logger).expect("logger should only be initialised once");
+    log::set_max_level(max_level);
+}
+
+/// Also write all log events to `path` as JSON lines, appending to it if it
+/// already exists.
+pub fn set_log_file(path: &Path, rotate_mb: Option<u64>) -> io::Result<()> {
+    let Some(logger) = LOGGER.get() else {
+        return Ok(());
+    };
+
+    let file = open_append(path)?;
+    *logger.file.lock().unwrap() = Some(LogFile {
+        path: path.to_path_buf(),
+        file,
+        rotate_bytes: rotate_mb.map(|mb| mb * 1024 * 1024),
+    });
+    log::set_max_level(log::max_level().max(LevelFilter::Debug));
+
+    Ok(())
+}
+
+/// Stop showing anything less important than warnings on stderr. The log file
+/// still gets everything.
+pub fn set_quiet() {
+    if let Some(logger) = LOGGER.get() {
+        logger.quiet.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Turn colour on or off for both log messages and progress bars. `Auto` only
+/// uses colour when stdout is a terminal and `NO_COLOR` isn't set.
+pub fn set_color(choice: ColorChoice) {
+    let enabled = match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && io::stdout().is_terminal()
+        }
+    };
+    console::set_colors_enabled(enabled);
+    console::set_colors_enabled_stderr(enabled);
+
+    if let Some(logger) = LOGGER.get() {
+        let style = if enabled {
+            WriteStyle::Always
+        } else {
+            WriteStyle::Never
+        };
+        *logger.inner.write().unwrap() = build_inner(Some(style));
+    }
+}
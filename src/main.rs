@@ -1,43 +1,96 @@
 mod api;
 mod cache;
 mod cmds;
+mod config;
 mod cookies;
+mod cue;
+mod error;
+mod logging;
+mod nfo;
+mod playlist;
 mod util;
+mod verify;
 
 #[macro_use]
 extern crate log;
 #[macro_use]
 extern crate simple_error;
 
-use clap::{Parser, Subcommand};
-use env_logger::{Env, DEFAULT_FILTER_ENV};
+use clap::{ColorChoice, CommandFactory, Parser, Subcommand};
 
 #[derive(Parser, Debug)]
 #[clap(name = "bandsnatch", version, about, long_about = None)]
 struct Args {
+    /// Path to a TOML file to load default option values from. Defaults to
+    /// `$XDG_CONFIG_HOME/bandsnatch/config.toml`.
+    #[arg(long, global = true, value_name = "PATH", env = "BS_CONFIG")]
+    config: Option<String>,
+
+    /// When to use colour in output. `auto` uses it when stdout is a terminal
+    /// and `NO_COLOR` isn't set.
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        value_name = "WHEN",
+        default_value_t = ColorChoice::Auto,
+        env = "BS_COLOR"
+    )]
+    color: ColorChoice,
+
     #[clap(subcommand)]
     command: Commands,
 }
 
+// Only ever constructed once, so size doesn't matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Subcommand)]
 enum Commands {
     /// Run Bandsnatch to download your collection.
     Run(cmds::run::Args),
+    /// Look at or manage the cache of downloaded releases.
+    Cache(cmds::cache::Args),
+    /// List which formats each release in your collection can be downloaded in.
+    ListFormats(cmds::list_formats::Args),
+    /// Work with the cookies used to log in to Bandcamp.
+    Cookies(cmds::cookies::Args),
+    /// Write details of every release in your collection to a CSV file.
+    Export(cmds::export::Args),
+    /// Print a shell completion script.
+    Completions(cmds::completions::Args),
     DebugCollection(cmds::debug_collection::Args), // Get the raw JSON of a specific Bandcamp release for debugging.
                                                    // Release(cmds::release::Args),
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // TODO: custom format
-    // TODO: make default based on what release target
-    let env = Env::default().filter_or(DEFAULT_FILTER_ENV, "bandsnatch=info");
-    env_logger::init_from_env(env);
+    logging::init();
+
+    // Do a lenient first pass to find `--config`, so that its values can be
+    // used as fallbacks for the real parse.
+    let cmd = Args::command();
+    let pre_matches = cmd.clone().ignore_errors(true).get_matches();
+    let config_path = pre_matches.get_one::<String>("config");
+    config::Config::load(config_path.map(String::as_str))?.apply(&cmd);
 
     // TODO: if no subcommands in env args, push `run` in front and parse from them.
     let args = Args::parse();
+    logging::set_color(args.color);
+    let config_path = args.config.clone();
 
     match args.command {
-        Commands::Run(cmd_args) => cmds::run::command(cmd_args),
+        Commands::Run(cmd_args) => cmds::run::command(cmd_args, || {
+            config::Config::load(config_path.as_deref())?.apply(&Args::command());
+            match Args::try_parse()?.command {
+                Commands::Run(cmd_args) => Ok(cmd_args),
+                _ => unreachable!(),
+            }
+        })
+        .map_err(Into::into),
+        Commands::Cache(cmd_args) => cmds::cache::command(cmd_args),
+        Commands::ListFormats(cmd_args) => cmds::list_formats::command(cmd_args),
+        Commands::Cookies(cmd_args) => cmds::cookies::command(cmd_args),
+        Commands::Export(cmd_args) => cmds::export::command(cmd_args),
+        Commands::Completions(cmd_args) => cmds::completions::command(cmd_args, Args::command()),
         Commands::DebugCollection(cmd_args) => cmds::debug_collection::command(cmd_args),
         // Commands::Release(cmd_args) => cmds::release::command(cmd_args).await,
     }
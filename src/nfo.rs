@@ -0,0 +1,65 @@
+use crate::api::structs::DigitalItem;
+
+/// Width of the box, including its borders.
+const WIDTH: usize = 78;
+
+fn border() -> String {
+    format!("+{}+\n", "-".repeat(WIDTH - 2))
+}
+
+/// A line inside of the box, cut short if it doesn't fit.
+fn line(text: &str) -> String {
+    let inner = WIDTH - 4;
+    let text = text.chars().take(inner).collect::<String>();
+    format!("| {text:<inner$} |\n")
+}
+
+fn centred(text: &str) -> String {
+    let inner = WIDTH - 4;
+    let text = text.chars().take(inner).collect::<String>();
+    format!("| {text:^inner$} |\n")
+}
+
+/// Details about a download that aren't part of the release itself.
+pub struct Download<'a> {
+    pub format: &'a str,
+    pub url: &'a str,
+    pub purchased: Option<&'a str>,
+}
+
+/// Render a scene-style NFO for `item`, as a fixed width ASCII box.
+pub fn render(item: &DigitalItem, download: &Download) -> String {
+    let mut nfo = border();
+    nfo += &centred(&format!("{} - {}", item.artist, item.title));
+    nfo += &border();
+
+    nfo += &line(&format!("Artist    : {}", item.artist));
+    nfo += &line(&format!("Title     : {}", item.title));
+    nfo += &line(&format!(
+        "Year      : {}",
+        item.release_year_or_purchase_year()
+    ));
+    nfo += &line(&format!("Format    : {}", download.format));
+    nfo += &line(&format!(
+        "Purchased : {}",
+        download.purchased.unwrap_or("unknown")
+    ));
+    nfo += &line(&format!("URL       : {}", download.url));
+
+    if !item.tracks.is_empty() {
+        nfo += &border();
+        nfo += &centred("Tracklist");
+        nfo += &border();
+        for (i, track) in item.tracks.iter().enumerate() {
+            let num = track.track_num.unwrap_or(i as u32 + 1);
+            let duration = track
+                .duration
+                .map(|d| format!(" ({}:{:02})", d as u64 / 60, d as u64 % 60))
+                .unwrap_or_default();
+            nfo += &line(&format!("{num:>2}. {}{duration}", track.title));
+        }
+    }
+
+    nfo += &border();
+    nfo
+}
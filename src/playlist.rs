@@ -0,0 +1,97 @@
+use crate::util;
+use clap::ValueEnum;
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Which kind of playlist file to write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PlaylistFormat {
+    /// Plain list of paths.
+    M3u,
+    /// Extended M3U, explicitly UTF-8.
+    M3u8,
+    /// PLS, as used by Winamp and friends.
+    Pls,
+}
+
+impl PlaylistFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            PlaylistFormat::M3u => "m3u",
+            PlaylistFormat::M3u8 => "m3u8",
+            PlaylistFormat::Pls => "pls",
+        }
+    }
+}
+
+/// Recursively find all audio files in `dir`, relative to `root`.
+fn find_audio_files(root: &Path, dir: &Path, found: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_audio_files(root, &path, found)?;
+            continue;
+        }
+
+        if util::is_audio_file(&path) {
+            if let Ok(relative) = path.strip_prefix(root) {
+                found.push(relative.to_path_buf());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Write a playlist of every audio file under `root` to
+/// `<root>/<user>-collection.<ext>`, returning where it was written. Paths are
+/// relative to `root`, so the playlist still works if the folder is moved.
+pub fn generate(root: &Path, user: &str, format: PlaylistFormat) -> io::Result<PathBuf> {
+    let mut files = vec![];
+    find_audio_files(root, root, &mut files)?;
+    files.sort();
+
+    // Forward slashes are understood by pretty much every player, on every
+    // platform.
+    let entries = files
+        .iter()
+        .map(|f| {
+            f.components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+        .collect::<Vec<_>>();
+
+    let path = root.join(format!("{user}-collection.{}", format.extension()));
+    let mut file = io::BufWriter::new(fs::File::create(&path)?);
+
+    match format {
+        PlaylistFormat::M3u => {
+            for entry in &entries {
+                writeln!(file, "{entry}")?;
+            }
+        }
+        PlaylistFormat::M3u8 => {
+            writeln!(file, "#EXTM3U")?;
+            for entry in &entries {
+                writeln!(file, "{entry}")?;
+            }
+        }
+        PlaylistFormat::Pls => {
+            writeln!(file, "[playlist]")?;
+            for (i, entry) in entries.iter().enumerate() {
+                writeln!(file, "File{}={entry}", i + 1)?;
+            }
+            writeln!(file, "NumberOfEntries={}", entries.len())?;
+            writeln!(file, "Version=2")?;
+        }
+    }
+    file.flush()?;
+
+    debug!("Wrote {} entries to {}", entries.len(), path.display());
+    Ok(path)
+}
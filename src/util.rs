@@ -1,9 +1,87 @@
+use crate::api::structs::DigitalItem;
+use crate::error::BandsnatchError;
+use clap::ValueEnum;
+use crossbeam_channel::Receiver;
+use indicatif::ProgressStyle;
 use phf::phf_map;
+use regex::{Regex, RegexBuilder};
+use sha2::{Digest, Sha256};
 use std::{
-    collections::VecDeque,
+    collections::HashSet,
+    fs::{self, File},
     io::{self, Read, Write},
-    sync::{Arc, Mutex},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
 };
+use unicode_normalization::UnicodeNormalization;
+
+/// Set when the user asks us to stop (e.g. through Ctrl-C), so that workers
+/// can stop picking up new work.
+pub static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Set when SIGHUP is received while `RELOAD_ON_HANGUP` is set, so that the
+/// daemon can re-read its config.
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// Whether SIGHUP should reload the config instead of stopping.
+static RELOAD_ON_HANGUP: AtomicBool = AtomicBool::new(false);
+static SHUTDOWN_HANDLER_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+fn on_shutdown_signal() {
+    if SHUTDOWN_REQUESTED.swap(true, Ordering::SeqCst) {
+        std::process::exit(130);
+    }
+    eprintln!(
+        "Finishing in-progress downloads before stopping. Press Ctrl-C again to stop immediately."
+    );
+}
+
+/// Install a SIGINT/SIGTERM/SIGHUP handler which sets `SHUTDOWN_REQUESTED`,
+/// or exits immediately if it was already set. Does nothing if it's already
+/// installed.
+pub fn install_shutdown_handler() -> io::Result<()> {
+    if SHUTDOWN_HANDLER_INSTALLED.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    ctrlc::set_handler(on_shutdown_signal).map_err(io::Error::other)?;
+
+    #[cfg(unix)]
+    {
+        use signal_hook::consts::{SIGHUP, SIGTERM};
+
+        let mut signals = signal_hook::iterator::Signals::new([SIGTERM, SIGHUP])?;
+        thread::spawn(move || {
+            for signal in signals.forever() {
+                if signal == SIGHUP && RELOAD_ON_HANGUP.load(Ordering::SeqCst) {
+                    RELOAD_REQUESTED.store(true, Ordering::SeqCst);
+                } else {
+                    on_shutdown_signal();
+                }
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Make SIGHUP ask for the config to be reloaded, instead of stopping.
+pub fn reload_on_hangup() {
+    RELOAD_ON_HANGUP.store(true, Ordering::SeqCst);
+}
+
+/// Whether SIGHUP was received since this was last called.
+pub fn take_reload_request() -> bool {
+    RELOAD_REQUESTED.swap(false, Ordering::SeqCst)
+}
+
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN_REQUESTED.load(Ordering::SeqCst)
+}
 
 // From https://github.com/Ezwen/bandcamp-collection-downloader/blob/master/src/main/kotlin/bandcampcollectiondownloader/core/Constants.kt#L7
 static REPLACEMENT_CHARS: phf::Map<&str, &str> = phf_map! {
@@ -23,19 +101,474 @@ static REPLACEMENT_CHARS: phf::Map<&str, &str> = phf_map! {
 static UNSAFE_NTFS_ENDINGS: &[char] = &['.', ' '];
 
 pub fn make_string_fs_safe(s: &str) -> String {
-    let mut str = s.to_string();
+    let mut str = s.chars().filter(|c| !c.is_control()).collect::<String>();
 
     for (from, to) in REPLACEMENT_CHARS.entries() {
         str = str.replace(from, to);
     }
 
-    if UNSAFE_NTFS_ENDINGS.contains(&str.chars().last().unwrap()) {
+    if str
+        .chars()
+        .last()
+        .is_none_or(|c| UNSAFE_NTFS_ENDINGS.contains(&c))
+    {
         str.push('_');
     }
 
     str
 }
 
+/// Unicode normalization form to apply to names before they're used in paths.
+/// Without this the same name may be written differently between releases
+/// and end up as separate folders, especially on macOS.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum UnicodeNorm {
+    #[default]
+    Nfc,
+    Nfd,
+    Nfkc,
+    Nfkd,
+    /// Leave names as they are.
+    None,
+}
+
+impl UnicodeNorm {
+    pub fn apply(self, s: &str) -> String {
+        match self {
+            UnicodeNorm::Nfc => s.nfc().collect(),
+            UnicodeNorm::Nfd => s.nfd().collect(),
+            UnicodeNorm::Nfkc => s.nfkc().collect(),
+            UnicodeNorm::Nfkd => s.nfkd().collect(),
+            UnicodeNorm::None => s.to_string(),
+        }
+    }
+}
+
+/// Which of a release's tags to use as its genre, with `--organize-by-genre`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum GenreSource {
+    #[default]
+    First,
+    Last,
+    /// The tag with the most words, like `melodic death metal` over `metal`.
+    MostSpecific,
+}
+
+/// Genre folder for releases without any tags.
+pub const UNKNOWN_GENRE: &str = "_unknown";
+
+/// Label folder for self-released releases.
+pub const INDEPENDENT_LABEL: &str = "_independent";
+
+/// Normalize `s` and make it safe to use as a single path component.
+/// Normalizing has to come first, as the compatibility forms would otherwise
+/// turn some of the replacement characters back into unsafe ones.
+pub fn make_path_component(s: &str, norm: UnicodeNorm) -> String {
+    make_string_fs_safe(&norm.apply(s))
+}
+
+/// Default `--max-path-len`, from Windows' `MAX_PATH`.
+pub const WINDOWS_MAX_PATH_LEN: usize = 259;
+
+/// Length of `path` once made absolute, which is what path limits apply to.
+pub fn absolute_path_len(path: &Path) -> usize {
+    std::path::absolute(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .to_string_lossy()
+        .chars()
+        .count()
+}
+
+/// Shorten the last component of `path` so the whole thing fits within
+/// `max_len` characters, adding a hash of the full name so that releases with
+/// similar names don't end up in the same folder.
+pub fn fit_path_len(path: &Path, max_len: usize) -> Result<PathBuf, String> {
+    let len = absolute_path_len(path);
+    if len <= max_len {
+        return Ok(path.to_path_buf());
+    }
+
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(format!("`{}` is too long to shorten", path.display()));
+    };
+    let name = name.to_string_lossy();
+    let hash = format!("{:x}", Sha256::digest(name.as_bytes()));
+    let suffix = format!("-{}", &hash[..8]);
+
+    let keep = (name.chars().count() + max_len)
+        .checked_sub(len + suffix.len())
+        .filter(|keep| *keep > 0)
+        .ok_or_else(|| {
+            format!(
+                "`{}` is too long to fit in {max_len} characters, even after shortening",
+                path.display()
+            )
+        })?;
+
+    let shortened = name.chars().take(keep).collect::<String>();
+    Ok(parent.join(format!("{}{suffix}", shortened.trim_end())))
+}
+
+/// Path that a file gets downloaded to before being moved to `path` once
+/// complete.
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+/// Path that a file being downloaded in chunks is put together at, before
+/// being moved to its `part_path` once every chunk is in.
+pub fn chunks_path(part_path: &Path) -> PathBuf {
+    part_path.with_extension("chunks")
+}
+
+/// Fresh, empty folder for a test to work in.
+#[cfg(test)]
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bandsnatch-test-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Variables that can be used in a `Template`.
+const TEMPLATE_VARIABLES: &[&str] = &["artist", "album", "year", "format", "id"];
+
+#[derive(Clone, Debug)]
+enum TemplatePart {
+    Literal(String),
+    Variable(String),
+}
+
+/// Template for where to put a release relative to the output folder, like
+/// `{artist}/{year}/{album}`. Each `/` separated component is made safe for
+/// the filesystem after being rendered.
+#[derive(Clone, Debug)]
+pub struct Template {
+    components: Vec<Vec<TemplatePart>>,
+}
+
+impl Template {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut components = vec![];
+
+        for component in s.split('/').filter(|c| !c.is_empty()) {
+            let mut parts = vec![];
+            let mut rest = component;
+
+            while let Some(start) = rest.find('{') {
+                if start > 0 {
+                    parts.push(TemplatePart::Literal(rest[..start].to_string()));
+                }
+                let end = rest[start..]
+                    .find('}')
+                    .ok_or_else(|| format!("Unclosed `{{` in template '{s}'."))?;
+                let name = &rest[start + 1..start + end];
+
+                if !TEMPLATE_VARIABLES.contains(&name) {
+                    return Err(format!(
+                        "Unknown variable `{{{name}}}` in template '{s}'. Valid variables are: {}.",
+                        TEMPLATE_VARIABLES
+                            .iter()
+                            .map(|v| format!("{{{v}}}"))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ));
+                }
+
+                parts.push(TemplatePart::Variable(name.to_string()));
+                rest = &rest[start + end + 1..];
+            }
+
+            if !rest.is_empty() {
+                parts.push(TemplatePart::Literal(rest.to_string()));
+            }
+            components.push(parts);
+        }
+
+        if components.is_empty() {
+            return Err(String::from("Template cannot be empty."));
+        }
+
+        Ok(Self { components })
+    }
+
+    pub fn render(&self, item: &DigitalItem, id: &str, format: &str, norm: UnicodeNorm) -> PathBuf {
+        self.components
+            .iter()
+            .map(|parts| {
+                let component = parts
+                    .iter()
+                    .map(|part| match part {
+                        TemplatePart::Literal(s) => s.clone(),
+                        TemplatePart::Variable(v) => match v.as_str() {
+                            "artist" => item.artist.clone(),
+                            "album" => item.title.clone(),
+                            "year" => item.release_year_or_purchase_year(),
+                            "format" => format.to_string(),
+                            "id" => id.to_string(),
+                            _ => unreachable!("variables are checked when parsing"),
+                        },
+                    })
+                    .collect::<String>();
+
+                make_path_component(&component, norm)
+            })
+            .collect()
+    }
+}
+
+const FILE_TEMPLATE_VARIABLES: &[&str] = &["track_number", "title", "artist", "album", "ext"];
+
+#[derive(Clone, Debug)]
+enum FileTemplatePart {
+    Literal(String),
+    /// Variable, and the width to zero pad it to.
+    Variable(String, Option<usize>),
+}
+
+/// Renames extracted audio files according to a template like
+/// `{track_number:02}-{title}.{ext}`, with details parsed from Bandcamp's
+/// `Artist - Album - 01 Title.ext` file names.
+#[derive(Clone, Debug)]
+pub struct FileRenamer {
+    parts: Vec<FileTemplatePart>,
+}
+
+impl FileRenamer {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut parts = vec![];
+        let mut rest = s;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(FileTemplatePart::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("Unclosed `{{` in template '{s}'."))?;
+            let variable = &rest[start + 1..start + end];
+            let (name, width) = match variable.split_once(':') {
+                Some((name, width)) => {
+                    let width = width
+                        .parse::<usize>()
+                        .map_err(|_| format!("Invalid width `{width}` in template '{s}'."))?;
+                    (name, Some(width))
+                }
+                None => (variable, None),
+            };
+
+            if !FILE_TEMPLATE_VARIABLES.contains(&name) {
+                return Err(format!(
+                    "Unknown variable `{{{name}}}` in template '{s}'. Valid variables are: {}.",
+                    FILE_TEMPLATE_VARIABLES
+                        .iter()
+                        .map(|v| format!("{{{v}}}"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+
+            parts.push(FileTemplatePart::Variable(name.to_string(), width));
+            rest = &rest[start + end + 1..];
+        }
+
+        if !rest.is_empty() {
+            parts.push(FileTemplatePart::Literal(rest.to_string()));
+        }
+        if parts.is_empty() {
+            return Err(String::from("Template cannot be empty."));
+        }
+
+        Ok(Self { parts })
+    }
+
+    /// Work out the new name for `file`, or `None` if the name isn't in the
+    /// format Bandcamp uses.
+    fn render(&self, file: &Path, item: &DigitalItem) -> Option<String> {
+        let stem = file.file_stem()?.to_string_lossy();
+        let ext = file.extension()?.to_string_lossy();
+
+        // Albums are `Artist - Album - 01 Title`, singles are `Artist - Title`.
+        static TRACK_NAME: OnceLock<Regex> = OnceLock::new();
+        let track_name = TRACK_NAME.get_or_init(|| Regex::new(r"^(.*) - (\d+) (.*)$").unwrap());
+        let (track_number, title) = match track_name.captures(&stem) {
+            Some(captures) => (captures[2].parse::<u32>().ok()?, captures[3].to_string()),
+            None => (1, stem.rsplit_once(" - ")?.1.to_string()),
+        };
+
+        let name = self
+            .parts
+            .iter()
+            .map(|part| match part {
+                FileTemplatePart::Literal(s) => s.clone(),
+                FileTemplatePart::Variable(name, width) => {
+                    let value = match name.as_str() {
+                        "track_number" => track_number.to_string(),
+                        "title" => title.clone(),
+                        "artist" => item.artist.clone(),
+                        "album" => item.title.clone(),
+                        "ext" => ext.to_string(),
+                        _ => unreachable!("variables are checked when parsing"),
+                    };
+                    format!("{value:0>width$}", width = width.unwrap_or(0))
+                }
+            })
+            .collect::<String>();
+
+        Some(make_string_fs_safe(&name))
+    }
+
+    /// Rename every audio file in `dir`, returning how many were renamed.
+    /// Files whose new name is already taken are left alone, rather than
+    /// replacing what's there.
+    pub fn rename_all(&self, dir: &Path, item: &DigitalItem) -> io::Result<usize> {
+        let mut files = vec![];
+        find_audio_files(dir, &mut files)?;
+
+        let mut taken = HashSet::new();
+        let mut renamed = 0;
+        for file in files {
+            let Some(name) = self.render(&file, item) else {
+                debug!("Not renaming `{}`, couldn't parse its name", file.display());
+                continue;
+            };
+
+            let new_path = file.with_file_name(name);
+            if new_path == file {
+                taken.insert(new_path);
+                continue;
+            }
+            // Names that only differ by case are the same file on some
+            // filesystems.
+            let same_file = new_path.exists()
+                && fs::canonicalize(&new_path).ok() == fs::canonicalize(&file).ok();
+            if taken.contains(&new_path) || (new_path.exists() && !same_file) {
+                warn!(
+                    "Not renaming `{}`, `{}` already exists",
+                    file.display(),
+                    new_path.display()
+                );
+                continue;
+            }
+
+            fs::rename(&file, &new_path)?;
+            taken.insert(new_path);
+            renamed += 1;
+        }
+
+        Ok(renamed)
+    }
+}
+
+/// Command to run after each release is downloaded, like
+/// `beet import -q {path}`. Split into arguments up front, so that values
+/// substituted in later can't be interpreted by a shell.
+#[derive(Clone, Debug)]
+pub struct HookCommand {
+    args: Vec<String>,
+}
+
+impl HookCommand {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let args = shell_words::split(s).map_err(|e| format!("Invalid command '{s}': {e}."))?;
+        if args.is_empty() {
+            return Err(String::from("Command cannot be empty."));
+        }
+
+        Ok(Self { args })
+    }
+
+    /// Run the command for `item`, which was downloaded to `path`, waiting for
+    /// it to finish.
+    pub fn run(
+        &self,
+        item: &DigitalItem,
+        id: &str,
+        format: &str,
+        path: &str,
+    ) -> io::Result<std::process::Output> {
+        let args = self
+            .args
+            .iter()
+            .map(|arg| {
+                arg.replace("{path}", path)
+                    .replace("{artist}", &item.artist)
+                    .replace("{title}", &item.title)
+                    .replace("{format}", format)
+                    .replace("{id}", id)
+            })
+            .collect::<Vec<_>>();
+
+        std::process::Command::new(&args[0])
+            .args(&args[1..])
+            .stdin(std::process::Stdio::null())
+            .output()
+    }
+}
+
+/// Check that `s` is a valid regex for filtering by, without compiling it for
+/// real until we know whether it should be case sensitive.
+pub fn parse_filter(s: &str) -> Result<String, String> {
+    Regex::new(s)
+        .map(|_| s.to_string())
+        .map_err(|e| format!("Invalid pattern '{s}': {e}"))
+}
+
+/// Compile a pattern checked by `parse_filter`.
+pub fn build_filter(pattern: &str, case_sensitive: bool) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(!case_sensitive)
+        .build()
+}
+
+/// Extensions of the audio files Bandcamp hands out.
+const AUDIO_EXTENSIONS: &[&str] = &["aac", "aif", "aiff", "flac", "m4a", "mp3", "ogg", "wav"];
+
+pub fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| AUDIO_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+}
+
+/// Recursively find all audio files in `dir`.
+pub fn find_audio_files(dir: &Path, found: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_audio_files(&path, found)?;
+        } else if is_audio_file(&path) {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Delete every audio file in `path` whose name doesn't match `track_pattern`
+/// (a case insensitive regex), leaving art and other files alone. Nothing is
+/// deleted if no tracks match.
+pub fn filter_extracted_files(path: &Path, track_pattern: &str) -> Result<(), BandsnatchError> {
+    let pattern = build_filter(track_pattern, false)?;
+    let mut files = vec![];
+    find_audio_files(path, &mut files)?;
+
+    let (matching, rest): (Vec<_>, Vec<_>) = files.into_iter().partition(|f| {
+        f.file_stem()
+            .is_some_and(|stem| pattern.is_match(&stem.to_string_lossy()))
+    });
+    if matching.is_empty() {
+        bail!("no tracks matching `{track_pattern}` in {}", path.display());
+    }
+
+    for file in rest {
+        debug!("Removing `{}`, doesn't match --track", file.display());
+        fs::remove_file(file)?;
+    }
+
+    Ok(())
+}
+
 pub fn slice_string(s: &str, amt: usize) -> &str {
     match s.char_indices().nth(amt) {
         Some((pos, _)) => &s[pos..],
@@ -43,68 +576,370 @@ pub fn slice_string(s: &str, amt: usize) -> &str {
     }
 }
 
-// Thanks to https://gist.github.com/NoraCodes/e6d40782b05dc8ac40faf3a0405debd3
+/// Queue of work shared between threads, which is filled once up front.
 #[derive(Clone)]
 pub struct WorkQueue<T> {
-    inner: Arc<Mutex<VecDeque<T>>>,
+    receiver: Receiver<T>,
+    /// Amount of items the queue started with, as they're removed when taken.
+    total: usize,
 }
 
 impl<T> WorkQueue<T> {
-    // pub fn new() -> Self {
-    //     Self {
-    //         inner: Arc::new(Mutex::new(VecDeque::new())),
-    //     }
-    // }
-
     pub fn from_vec(vec: Vec<T>) -> Self {
-        Self {
-            inner: Arc::new(Mutex::new(VecDeque::from(vec))),
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let total = vec.len();
+        for item in vec {
+            sender
+                .send(item)
+                .expect("receiver is alive for the whole function");
         }
+
+        Self { receiver, total }
+    }
+
+    /// Amount of items that were ever put in the queue.
+    pub fn len(&self) -> usize {
+        self.total
+    }
+
+    /// Whether every item has been taken from the queue.
+    pub fn is_empty(&self) -> bool {
+        self.receiver.is_empty()
+    }
+
+    /// Amount of items that have been taken from the queue, including ones
+    /// still being worked on.
+    pub fn completed(&self) -> usize {
+        self.total - self.remaining()
+    }
+
+    /// Amount of items that haven't been taken from the queue yet.
+    pub fn remaining(&self) -> usize {
+        self.receiver.len()
     }
 
     pub fn get_work(&self) -> Option<T> {
-        // Try to get a lock on the Mutex. If this fails, there is a
-        // problem with the mutex - it's poisoned, meaning that a thread that
-        // held the mutex lock panicked before releasing it. There is no way
-        // to guarantee that all its invariants are upheld, so we need to not
-        // use it in that case.
-        let maybe_queue = self.inner.lock();
-        // A lot is going on here. self.inner is an Arc of Mutex. Arc can deref
-        // into its internal type, so we can call the methods of that inner
-        // type (Mutex) without dereferencing, so this is like
-        //      *(self.inner).lock()
-        // but doesn't look awful. Mutex::lock() returns a
-        // Result<MutexGuard<VecDeque<T>>>.
-
-        // Unwrapping with if let, we get a MutexGuard, which is an RAII guard
-        // that unlocks the Mutex when it goes out of scope.
-        if let Ok(mut queue) = maybe_queue {
-            // queue is a MutexGuard<VecDeque>, so this is like
-            //      (*queue).pop_front()
-            // Returns Some(item) or None if there are no more items.
-            queue.pop_front()
-
-            // The function has returned, so queue goes out of scope and the
-            // mutex unlocks.
+        // The sender is dropped once everything has been sent, so this only
+        // fails once the queue is empty.
+        self.receiver.try_recv().ok()
+    }
+}
+
+/// Limits how many threads can do something at once, like talking to
+/// Bandcamp's APIs.
+pub struct Semaphore {
+    permits: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Held while doing whatever's limited by a `Semaphore`, letting the next
+/// thread in once dropped.
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Self {
+        Self {
+            permits: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Wait until there's a permit free, and take it.
+    pub fn acquire(&self) -> SemaphorePermit<'_> {
+        let mut permits = self.permits.lock().unwrap();
+        while *permits == 0 {
+            permits = self.released.wait(permits).unwrap();
+        }
+        *permits -= 1;
+        SemaphorePermit { semaphore: self }
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        *self.semaphore.permits.lock().unwrap() += 1;
+        self.semaphore.released.notify_one();
+    }
+}
+
+/// Something that can unpack an album's archive.
+pub trait Extractor {
+    /// Extract everything in `archive` into `dest`.
+    fn extract(&self, archive: &Path, dest: &Path) -> Result<(), BandsnatchError>;
+}
+
+/// Which `Extractor` to unpack albums with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ExtractorKind {
+    /// The built-in extractor, falling back to `7z` for archives it can't
+    /// handle.
+    #[default]
+    Auto,
+    /// The built-in extractor.
+    Zip,
+    /// The system's `7z`, from p7zip or 7-Zip.
+    P7zip,
+    /// The system's `unzip`.
+    Unzip,
+}
+
+impl ExtractorKind {
+    pub fn extractor(self) -> &'static dyn Extractor {
+        match self {
+            ExtractorKind::Auto => &AutoExtractor,
+            ExtractorKind::Zip => &ZipExtractor,
+            ExtractorKind::P7zip => &SevenZipExtractor,
+            ExtractorKind::Unzip => &UnzipExtractor,
+        }
+    }
+}
+
+/// Extracts archives with the `zip` crate.
+pub struct ZipExtractor;
+
+impl ZipExtractor {
+    fn try_extract(archive: &Path, dest: &Path) -> zip::result::ZipResult<()> {
+        let reader = io::BufReader::new(File::open(archive)?);
+        zip::ZipArchive::new(reader)?.extract(dest)
+    }
+}
+
+impl Extractor for ZipExtractor {
+    fn extract(&self, archive: &Path, dest: &Path) -> Result<(), BandsnatchError> {
+        Ok(Self::try_extract(archive, dest)?)
+    }
+}
+
+/// Run an external extractor, turning a failed exit into an error.
+fn run_extractor(mut cmd: std::process::Command) -> Result<(), BandsnatchError> {
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let output = cmd
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to run `{program}`: {e}")))?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "`{program}` failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Extracts archives with `7z`, which handles more compression methods than
+/// the `zip` crate.
+pub struct SevenZipExtractor;
+
+impl Extractor for SevenZipExtractor {
+    fn extract(&self, archive: &Path, dest: &Path) -> Result<(), BandsnatchError> {
+        let mut out_arg = std::ffi::OsString::from("-o");
+        out_arg.push(dest);
+
+        let mut cmd = std::process::Command::new("7z");
+        cmd.arg("x").arg("-y").arg(out_arg).arg(archive);
+        run_extractor(cmd)
+    }
+}
+
+/// Extracts archives with Info-ZIP's `unzip`.
+pub struct UnzipExtractor;
+
+impl Extractor for UnzipExtractor {
+    fn extract(&self, archive: &Path, dest: &Path) -> Result<(), BandsnatchError> {
+        let mut cmd = std::process::Command::new("unzip");
+        cmd.arg("-o").arg("-q").arg(archive).arg("-d").arg(dest);
+        run_extractor(cmd)
+    }
+}
+
+/// Extracts with `ZipExtractor`, and then `SevenZipExtractor` if the archive
+/// uses something the `zip` crate doesn't support.
+pub struct AutoExtractor;
+
+impl Extractor for AutoExtractor {
+    fn extract(&self, archive: &Path, dest: &Path) -> Result<(), BandsnatchError> {
+        match ZipExtractor::try_extract(archive, dest) {
+            Err(zip::result::ZipError::UnsupportedArchive(reason)) => {
+                debug!("Can't extract with the zip crate ({reason}), trying 7z");
+                SevenZipExtractor.extract(archive, dest)
+            }
+            result => Ok(result?),
+        }
+    }
+}
+
+/// Call `f` until it succeeds, retrying up to `retries` times as long as
+/// `should_retry` says the error is worth retrying. The delay between attempts
+/// starts at `delay` and doubles after every failure. `on_retry` is called with
+/// the error, the attempt number, and the upcoming delay before each retry.
+pub fn retry_with_backoff<T, E, F, S, R>(
+    retries: u32,
+    delay: Duration,
+    mut f: F,
+    should_retry: S,
+    mut on_retry: R,
+) -> Result<T, E>
+where
+    F: FnMut() -> Result<T, E>,
+    S: Fn(&E) -> bool,
+    R: FnMut(&E, u32, Duration),
+{
+    let mut attempt = 0;
+
+    loop {
+        match f() {
+            Ok(val) => return Ok(val),
+            Err(e) if attempt < retries && should_retry(&e) => {
+                attempt += 1;
+                // Cap the exponent so that silly retry counts can't overflow.
+                let wait = delay.saturating_mul(1 << (attempt - 1).min(16));
+                on_retry(&e, attempt, wait);
+                thread::sleep(wait);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Calculate the SHA-256 hash of a file's contents.
+pub fn sha256_file(path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Parse a human friendly amount of bytes per second, like `500k` or `2m`.
+/// Suffixes are binary, so `1k` is 1024 bytes.
+pub fn parse_rate(s: &str) -> Result<u64, String> {
+    parse_bytes(s, "Rate")
+}
+
+/// Parse a human friendly file size, like `100k` or `2g`, the same way as
+/// `parse_rate`.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    parse_bytes(s, "Size")
+}
+
+fn parse_bytes(s: &str, what: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (num, multiplier) = match s.chars().last().map(|c| c.to_ascii_lowercase()) {
+        Some('k') => (&s[..s.len() - 1], 1024),
+        Some('m') => (&s[..s.len() - 1], 1024 * 1024),
+        Some('g') => (&s[..s.len() - 1], 1024 * 1024 * 1024),
+        _ => (s, 1),
+    };
+
+    match num.trim().parse::<u64>() {
+        Ok(0) => Err(format!("{what} must be greater than 0.")),
+        Ok(n) => n
+            .checked_mul(multiplier)
+            .ok_or_else(|| format!("{what} '{s}' is too large.")),
+        Err(_) => Err(format!(
+            "Invalid {} '{s}'. Use a number of bytes, optionally followed by k, m, or g.",
+            what.to_lowercase()
+        )),
+    }
+}
+
+/// Token bucket for limiting throughput, refilling at `rate` bytes per second
+/// with a burst of up to one second's worth.
+pub struct TokenBucket {
+    rate: u64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            tokens: rate as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take `amount` tokens from the bucket, returning how long the caller
+    /// needs to wait until they would have been available.
+    pub fn take(&mut self, amount: u64) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+        self.tokens -= amount as f64;
+
+        if self.tokens >= 0.0 {
+            Duration::ZERO
         } else {
-            // There's a problem with the mutex.
-            panic!("WorkQueue::get_work() tried to lock a poisoned mutex");
+            Duration::from_secs_f64(-self.tokens / self.rate as f64)
         }
     }
 }
 
+/// Reader that sleeps as needed to stay under the rate of a shared
+/// `TokenBucket`.
+pub struct ThrottledReader<R> {
+    inner: R,
+    bucket: Arc<Mutex<TokenBucket>>,
+}
+
+impl<R> ThrottledReader<R> {
+    pub fn new(inner: R, bucket: Arc<Mutex<TokenBucket>>) -> Self {
+        Self { inner, bucket }
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        // Sleep outside of the lock so other downloads can take their share.
+        let wait = self.bucket.lock().unwrap().take(len as u64);
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+        Ok(len)
+    }
+}
+
+/// Style for a worker's progress bar while it's downloading.
+pub fn download_style() -> ProgressStyle {
+    ProgressStyle::with_template(
+        "{prefix} {bar:20} {binary_bytes}/{binary_total_bytes} ({binary_bytes_per_sec}, {eta}) {wide_msg}",
+    )
+    .unwrap()
+}
+
+/// Style for the bar summarising every worker's progress.
+pub fn summary_style() -> ProgressStyle {
+    ProgressStyle::with_template("{prefix:.bold} {bar:20} {pos}/{len} ({eta}) {wide_msg}").unwrap()
+}
+
+/// Style for a worker's progress bar after its last download succeeded.
+pub fn done_style() -> ProgressStyle {
+    ProgressStyle::with_template("{prefix} {msg:.green}").unwrap()
+}
+
+/// Style for a worker's progress bar after its last download failed.
+pub fn failed_style() -> ProgressStyle {
+    ProgressStyle::with_template("{prefix} {msg:.red}").unwrap()
+}
+
 const DEFAULT_BUF_SIZE: usize = 8192;
 
 // `std::io::copy` slightly modified to update a progress bar as it copies
 // https://doc.rust-lang.org/1.8.0/src/std/up/src/libstd/io/util.rs.html#46-61
-pub fn copy_with_progress<R: ?Sized, W: ?Sized>(
+pub fn copy_with_progress<R, W>(
     reader: &mut R,
     writer: &mut W,
-    pb: &indicatif::ProgressBar,
+    pb: Option<&indicatif::ProgressBar>,
 ) -> io::Result<u64>
 where
-    R: Read,
-    W: Write,
+    R: Read + ?Sized,
+    W: Write + ?Sized,
 {
     let mut buf = [0; DEFAULT_BUF_SIZE];
     let mut written = 0;
@@ -117,6 +952,62 @@ where
         };
         writer.write_all(&buf[..len])?;
         written += len as u64;
-        pb.set_position(written);
+        if let Some(pb) = pb {
+            pb.inc(len as u64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// "Björk", with the ö as a single character.
+    const PRECOMPOSED: &str = "Bj\u{f6}rk";
+    /// "Björk", with the ö as an o and a combining diaeresis.
+    const DECOMPOSED: &str = "Bjo\u{308}rk";
+
+    #[test]
+    fn path_component_nfc_joins_accents() {
+        assert_eq!(
+            make_path_component(PRECOMPOSED, UnicodeNorm::Nfc),
+            PRECOMPOSED
+        );
+        assert_eq!(
+            make_path_component(DECOMPOSED, UnicodeNorm::Nfc),
+            PRECOMPOSED
+        );
+    }
+
+    #[test]
+    fn path_component_nfd_splits_accents() {
+        assert_eq!(
+            make_path_component(PRECOMPOSED, UnicodeNorm::Nfd),
+            DECOMPOSED
+        );
+        assert_eq!(
+            make_path_component(DECOMPOSED, UnicodeNorm::Nfd),
+            DECOMPOSED
+        );
+    }
+
+    #[test]
+    fn path_component_none_leaves_names_alone() {
+        assert_eq!(
+            make_path_component(PRECOMPOSED, UnicodeNorm::None),
+            PRECOMPOSED
+        );
+        assert_eq!(
+            make_path_component(DECOMPOSED, UnicodeNorm::None),
+            DECOMPOSED
+        );
+    }
+
+    #[test]
+    fn path_component_strips_control_characters() {
+        assert_eq!(
+            make_path_component("Bj\u{f6}rk\u{0}\t\n", UnicodeNorm::Nfc),
+            PRECOMPOSED
+        );
     }
 }
@@ -0,0 +1,80 @@
+use crate::util;
+use std::{
+    fs::{self, File},
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+};
+
+/// A file that failed its check.
+pub struct Problem {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+#[derive(Default)]
+pub struct Report {
+    /// Amount of files that were checked.
+    pub checked: usize,
+    pub problems: Vec<Problem>,
+}
+
+/// Check everything that's been downloaded to `root`. Zips kept with
+/// `--keep-zip` are read in full so every file's CRC gets checked, and audio
+/// files are checked for being empty, which is what a download that was cut
+/// short before extracting leaves behind.
+pub fn recheck(root: &Path) -> io::Result<Report> {
+    let mut report = Report::default();
+    recheck_dir(root, &mut report)?;
+    Ok(report)
+}
+
+fn recheck_dir(dir: &Path, report: &mut Report) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            recheck_dir(&path, report)?;
+            continue;
+        }
+
+        let result = if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("zip"))
+        {
+            check_zip(&path)
+        } else if util::is_audio_file(&path) {
+            check_audio_file(&path)
+        } else {
+            continue;
+        };
+
+        report.checked += 1;
+        if let Err(reason) = result {
+            report.problems.push(Problem { path, reason });
+        }
+    }
+
+    Ok(())
+}
+
+fn check_zip(path: &Path) -> Result<(), String> {
+    let reader = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let mut archive = zip::ZipArchive::new(reader).map_err(|e| format!("not a valid zip: {e}"))?;
+
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(|e| e.to_string())?;
+        let name = file.name().to_string();
+        // The CRC is only checked once the whole file has been read.
+        io::copy(&mut file, &mut io::sink()).map_err(|e| format!("`{name}` is corrupt: {e}"))?;
+    }
+
+    Ok(())
+}
+
+fn check_audio_file(path: &Path) -> Result<(), String> {
+    let len = fs::metadata(path).map_err(|e| e.to_string())?.len();
+    if len == 0 {
+        return Err(String::from("file is empty"));
+    }
+
+    Ok(())
+}